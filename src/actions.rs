@@ -19,6 +19,24 @@ impl ElectionId {
     }
 }
 
+impl From<usize> for ElectionId {
+    fn from(value: usize) -> Self {
+        ElectionId(value)
+    }
+}
+
+impl From<ElectionId> for usize {
+    fn from(value: ElectionId) -> Self {
+        value.0
+    }
+}
+
+impl std::fmt::Display for ElectionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 impl ActionId for ElectionId {
     fn get_id(&self) -> Either<&ElectionId, &VoteId> {
         Either::Left(self)
@@ -80,6 +98,9 @@ impl From<ElectionAction> for ElectionId {
 pub(crate) enum ElectionActionType {
     InitiateVote,
     GetResult,
+    Close,
+    Certify,
+    Announce,
 }
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
@@ -105,6 +126,8 @@ pub(crate) enum VoteActionType {
     ConfirmInitiateVote,
     SelectVote,
     SkipVote,
+    Approve,
+    Reject,
     CancelVote,
     VoidBallot,
 }
@@ -145,6 +168,18 @@ impl Action {
                     .label("Results")
                     .style(serenity::ButtonStyle::Secondary)
                     .emoji(react("🧮")),
+                ElectionActionType::Close => btn
+                    .label("Close Voting")
+                    .style(serenity::ButtonStyle::Danger)
+                    .emoji(react("🔒")),
+                ElectionActionType::Certify => btn
+                    .label("Certify Results")
+                    .style(serenity::ButtonStyle::Success)
+                    .emoji(react("✅")),
+                ElectionActionType::Announce => btn
+                    .label("Announce Results")
+                    .style(serenity::ButtonStyle::Secondary)
+                    .emoji(react("📣")),
             },
             Action::Vote(VoteAction { ty, .. }) => match ty {
                 VoteActionType::ConfirmInitiateVote => btn
@@ -160,6 +195,14 @@ impl Action {
                     .style(serenity::ButtonStyle::Secondary)
                     .emoji(react("🤷"))
                     .label("Skip"),
+                VoteActionType::Approve => btn
+                    .style(serenity::ButtonStyle::Success)
+                    .emoji(react("👍"))
+                    .label("Approve"),
+                VoteActionType::Reject => btn
+                    .style(serenity::ButtonStyle::Secondary)
+                    .emoji(react("👎"))
+                    .label("Reject"),
                 VoteActionType::VoidBallot => btn
                     .style(serenity::ButtonStyle::Danger)
                     .emoji(react("🛑"))
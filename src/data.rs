@@ -1,4 +1,8 @@
-use std::{collections::BTreeMap, path::Path};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    sync::Arc,
+};
 
 use anyhow::Context as _;
 use chrono::Utc;
@@ -6,16 +10,85 @@ use poise::serenity_prelude as serenity;
 use serde::{Deserialize, Serialize};
 use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
+mod sealing;
+mod sqlite;
+
+pub use sealing::DataKey;
+pub use sqlite::{rotate_database_key, Row, Rows, Table};
+
 pub trait Migrate {
     fn migrate(&mut self);
 }
 
+/// Where the data is kept between runs.
+pub trait Storage<GuildData>: std::fmt::Debug + Send + Sync {
+    /// Saves the data. Only the guilds in `changed` can differ from the last successful save.
+    fn save(
+        &self,
+        data: &GlobalData<GuildData>,
+        changed: &BTreeSet<serenity::GuildId>,
+    ) -> Result<(), anyhow::Error>;
+
+    /// Waits for earlier saves to reach the disk.
+    fn flush(&self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
 #[derive(Default, Debug, Serialize, Deserialize)]
 pub struct GlobalData<GuildData> {
     guilds: BTreeMap<serenity::GuildId, GuildData>,
+    /// Where `persist` saves to. Nothing is saved without one.
+    #[serde(skip)]
+    storage: Option<Box<dyn Storage<GuildData>>>,
+    /// Guilds handed out for changing since the last save.
+    #[serde(skip)]
+    changed: BTreeSet<serenity::GuildId>,
+}
+
+/// The whole data in one JSON file, `{path_base}.json`, copied into `bku/` on every save.
+#[derive(Debug)]
+struct JsonFile {
+    path_base: String,
+    /// Seals the data file and its backups when set.
+    key: Option<DataKey>,
+}
+
+/// Reads `{path_base}.json`, decrypting it if it is sealed, or starts afresh if there is no data
+/// file.
+fn read_json<GuildData>(
+    path_base: &str,
+    key: Option<&DataKey>,
+) -> Result<GlobalData<GuildData>, anyhow::Error>
+where
+    GuildData: serde::de::DeserializeOwned + Default,
+{
+    match std::fs::read(format!("{path_base}.json")) {
+        Ok(contents) => {
+            let plain = sealing::open(&contents, key)?;
+            serde_json::from_slice(&plain).context("while parsing data file")
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(GlobalData::default()),
+        Err(e) => Err(e).context("while reading data file"),
+    }
 }
 
 impl<GuildData> GlobalData<GuildData> {
+    /// Reads the data from `{path_base}.json` and saves it there from then on, sealed with `key`
+    /// if there is one.
+    pub fn load_json<S: AsRef<str>>(
+        path_base: S,
+        key: Option<DataKey>,
+    ) -> Result<Self, anyhow::Error>
+    where
+        GuildData: serde::de::DeserializeOwned + Serialize + Default + 'static,
+    {
+        let path_base = path_base.as_ref().to_owned();
+        let mut data = read_json(&path_base, key.as_ref())?;
+        data.storage = Some(Box::new(JsonFile { path_base, key }));
+        Ok(data)
+    }
+
     pub fn migrate(&mut self)
     where
         GuildData: Migrate,
@@ -23,14 +96,21 @@ impl<GuildData> GlobalData<GuildData> {
         self.guilds.values_mut().for_each(Migrate::migrate);
     }
 
+    #[allow(unused)]
     pub fn guild(&self, id: serenity::GuildId) -> Option<&GuildData> {
         self.guilds.get(&id)
     }
 
+    pub fn guilds_mut(&mut self) -> impl Iterator<Item = (serenity::GuildId, &mut GuildData)> {
+        self.changed.extend(self.guilds.keys());
+        self.guilds.iter_mut().map(|(id, guild)| (*id, guild))
+    }
+
     pub fn guild_mut(&mut self, guild_id: serenity::GuildId) -> &mut GuildData
     where
         GuildData: Default,
     {
+        self.changed.insert(guild_id);
         self.guilds.entry(guild_id).or_default()
     }
 }
@@ -62,11 +142,32 @@ fn persist_folder<S: AsRef<str>, P: AsRef<Path>, P2: AsRef<Path>>(
 }
 
 impl<GuildData> GlobalData<GuildData> {
-    pub fn persist<S: AsRef<str>>(&self, path_base: S) -> Result<(), anyhow::Error>
-    where
-        GuildData: Serialize,
-    {
-        let path_base = path_base.as_ref();
+    pub fn persist(&mut self) -> Result<(), anyhow::Error> {
+        let Some(storage) = &self.storage else {
+            return Ok(());
+        };
+        storage.save(self, &self.changed)?;
+        self.changed.clear();
+        Ok(())
+    }
+
+    /// Saves the data and waits for it to reach the disk, for shutting down.
+    pub fn flush(&mut self) -> Result<(), anyhow::Error> {
+        self.persist()?;
+        match &self.storage {
+            Some(storage) => storage.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<GuildData: Serialize> Storage<GuildData> for JsonFile {
+    fn save(
+        &self,
+        data: &GlobalData<GuildData>,
+        _changed: &BTreeSet<serenity::GuildId>,
+    ) -> Result<(), anyhow::Error> {
+        let path_base = self.path_base.as_str();
         let now = Utc::now();
         persist_folder(
             path_base,
@@ -75,9 +176,12 @@ impl<GuildData> GlobalData<GuildData> {
             20,
         )?;
 
-        let mut output = std::fs::File::create(format!("{}.json", path_base))
-            .context("while opening data file")?;
-        serde_json::to_writer_pretty(&mut output, self).context("while formatting json")?;
+        let mut contents = serde_json::to_vec_pretty(data).context("while formatting json")?;
+        if let Some(key) = &self.key {
+            contents = key.seal(&contents)?;
+        }
+        std::fs::write(format!("{}.json", path_base), contents)
+            .context("while writing data file")?;
 
         persist_folder(
             path_base,
@@ -102,14 +206,71 @@ impl<GuildData> GlobalData<GuildData> {
     }
 }
 
+/// Re-seals `{path_base}.json` and every JSON backup under `bku/` with `new`, opening them with
+/// `old`. Either key may be `None`, to encrypt plain files for the first time or to go back to
+/// plain files. Returns how many files were rewritten.
+pub fn rotate_key<S: AsRef<str>>(
+    path_base: S,
+    old: Option<&DataKey>,
+    new: Option<&DataKey>,
+) -> Result<usize, anyhow::Error> {
+    let mut files = vec![std::path::PathBuf::from(format!(
+        "{}.json",
+        path_base.as_ref()
+    ))];
+    let mut folders = vec![std::path::PathBuf::from("bku")];
+    while let Some(folder) = folders.pop() {
+        if !folder.is_dir() {
+            continue;
+        }
+        for entry in std::fs::read_dir(&folder)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                folders.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+
+    // Open everything before rewriting anything, so a wrong key leaves the files as they were.
+    let mut opened = Vec::new();
+    // Database backups share the folder, and seal their rows rather than the whole file.
+    let json = |p: &std::path::PathBuf| p.is_file() && p.extension() == Some("json".as_ref());
+    for path in files.into_iter().filter(json) {
+        let plain = sealing::open_any(&std::fs::read(&path)?, old)
+            .with_context(|| format!("while opening {}", path.display()))?;
+        opened.push((path, plain));
+    }
+    for (path, plain) in &opened {
+        let contents = match new {
+            Some(key) => key.seal(plain)?,
+            None => plain.clone(),
+        };
+        let temp = path.with_extension("json.tmp");
+        std::fs::write(&temp, contents)?;
+        std::fs::rename(&temp, path)?;
+    }
+    Ok(opened.len())
+}
+
+/// Shared handle to the bot's data. Clones refer to the same data.
 pub struct GlobalState<GuildData> {
-    data: RwLock<GlobalData<GuildData>>,
+    data: Arc<RwLock<GlobalData<GuildData>>>,
+}
+
+impl<D> Clone for GlobalState<D> {
+    fn clone(&self) -> Self {
+        Self {
+            data: self.data.clone(),
+        }
+    }
 }
 
 impl<D> GlobalState<D> {
     pub fn new(data: GlobalData<D>) -> Self {
         Self {
-            data: RwLock::new(data),
+            data: Arc::new(RwLock::new(data)),
         }
     }
 
@@ -0,0 +1,158 @@
+//! Encryption of the data file and its backups at rest.
+//!
+//! A sealed file is still JSON, holding a nonce and the ChaCha20-Poly1305 ciphertext of the
+//! plain data file, both in hex. Once a key is set, files that aren't sealed are refused, so
+//! nobody can swap in data of their own. `rotate-data-key` seals existing plain files.
+
+use anyhow::Context as _;
+use ring::{aead, rand::SecureRandom as _};
+use serde::{Deserialize, Serialize};
+
+/// The key data files are sealed with.
+pub struct DataKey(aead::LessSafeKey);
+
+impl std::fmt::Debug for DataKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DataKey(..)")
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Sealed {
+    sealed: u32,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl DataKey {
+    /// The environment variable holding the key.
+    pub const VAR: &'static str = "DATA_KEY";
+    /// Where the replacement key is read from when rotating.
+    pub const NEW_VAR: &'static str = "NEW_DATA_KEY";
+
+    /// Parses a key of 64 hex digits, such as the output of `openssl rand -hex 32`.
+    pub fn parse(hex: &str) -> Result<DataKey, anyhow::Error> {
+        let bytes = from_hex(hex.trim())
+            .filter(|b| b.len() == aead::CHACHA20_POLY1305.key_len())
+            .ok_or_else(|| anyhow::anyhow!("The data key should be 64 hex digits"))?;
+        let key = aead::UnboundKey::new(&aead::CHACHA20_POLY1305, &bytes)
+            .map_err(|_| anyhow::anyhow!("The data key is invalid"))?;
+        Ok(DataKey(aead::LessSafeKey::new(key)))
+    }
+
+    /// Reads the key from the environment variable, if it is set.
+    pub fn from_env(var: &str) -> Result<Option<DataKey>, anyhow::Error> {
+        match std::env::var(var) {
+            Ok(hex) if !hex.is_empty() => DataKey::parse(&hex)
+                .with_context(|| format!("reading {var}"))
+                .map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn seal(&self, plain: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        let mut nonce = [0; aead::NONCE_LEN];
+        ring::rand::SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow::anyhow!("couldn't generate a nonce"))?;
+        let mut ciphertext = plain.to_vec();
+        self.0
+            .seal_in_place_append_tag(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::empty(),
+                &mut ciphertext,
+            )
+            .map_err(|_| anyhow::anyhow!("couldn't encrypt the data"))?;
+        let sealed = Sealed {
+            sealed: 1,
+            nonce: to_hex(&nonce),
+            ciphertext: to_hex(&ciphertext),
+        };
+        Ok(serde_json::to_vec(&sealed)?)
+    }
+}
+
+/// The plain contents of a data file, decrypting it with `key`. Without a key only plain files
+/// can be read, and with one only sealed files.
+pub fn open(contents: &[u8], key: Option<&DataKey>) -> Result<Vec<u8>, anyhow::Error> {
+    if key.is_some() && serde_json::from_slice::<Sealed>(contents).is_err() {
+        anyhow::bail!(
+            "The data isn't encrypted, but {} is set. Run rotate-data-key with {} set to \
+             encrypt it",
+            DataKey::VAR,
+            DataKey::NEW_VAR
+        );
+    }
+    open_any(contents, key)
+}
+
+/// Like `open`, but also reads plain files when a key is set, for sealing them when keys are
+/// rotated.
+pub fn open_any(contents: &[u8], key: Option<&DataKey>) -> Result<Vec<u8>, anyhow::Error> {
+    let Ok(sealed) = serde_json::from_slice::<Sealed>(contents) else {
+        return Ok(contents.to_vec());
+    };
+    let key = key
+        .ok_or_else(|| anyhow::anyhow!("The data is encrypted, but {} isn't set", DataKey::VAR))?;
+    let nonce = from_hex(&sealed.nonce)
+        .and_then(|n| aead::Nonce::try_assume_unique_for_key(&n).ok())
+        .ok_or_else(|| anyhow::anyhow!("The encrypted data has a bad nonce"))?;
+    let mut plain = from_hex(&sealed.ciphertext)
+        .ok_or_else(|| anyhow::anyhow!("The encrypted data isn't hex"))?;
+    let len = key
+        .0
+        .open_in_place(nonce, aead::Aad::empty(), &mut plain)
+        .map_err(|_| anyhow::anyhow!("The data couldn't be decrypted with this key"))?
+        .len();
+    plain.truncate(len);
+    Ok(plain)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    #[test]
+    fn test_sealed_data_opens_with_its_key() {
+        let key = DataKey::parse(KEY).unwrap();
+        let sealed = key.seal(br#"{"guilds":{}}"#).unwrap();
+        assert!(!String::from_utf8_lossy(&sealed).contains("guilds"));
+        assert_eq!(open(&sealed, Some(&key)).unwrap(), br#"{"guilds":{}}"#);
+
+        let other = DataKey::parse(&KEY.replace("00", "ff")).unwrap();
+        assert!(open(&sealed, Some(&other)).is_err());
+        assert!(open(&sealed, None).is_err());
+    }
+
+    #[test]
+    fn test_plain_data_is_refused_once_there_is_a_key() {
+        let key = DataKey::parse(KEY).unwrap();
+        assert!(open(b"{}", Some(&key)).is_err());
+        assert_eq!(open(b"{}", None).unwrap(), b"{}");
+        // Rotating keys seals plain files.
+        assert_eq!(open_any(b"{}", Some(&key)).unwrap(), b"{}");
+    }
+
+    #[test]
+    fn test_parse_key() {
+        assert!(DataKey::parse(&KEY[2..]).is_err());
+        assert!(DataKey::parse(&KEY.replace('a', "g")).is_err());
+        assert!(DataKey::parse(&format!(" {KEY}\n")).is_ok());
+    }
+}
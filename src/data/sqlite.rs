@@ -0,0 +1,631 @@
+//! Storage in a SQLite database, a row per election, ballot and vote in progress.
+//!
+//! Saving turns the guilds that may have changed into rows, compares each row's JSON with what
+//! was last written and only writes the rows that changed. The writes happen on a thread of their own, so callers holding the data lock don't
+//! wait on the disk; rows whose write fails are written again by the next save, and `flush`
+//! waits for the writer to catch up. The database is copied into `bku/database/` once a day.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+};
+
+use anyhow::Context as _;
+use chrono::Utc;
+use poise::serenity_prelude as serenity;
+use rusqlite::{params, Connection, OpenFlags};
+
+use super::{read_json, sealing, DataKey, GlobalData, Storage};
+
+/// The tables rows are kept in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Table {
+    /// What's left of a guild's data once the other tables are taken out, keyed by `""`.
+    Guilds,
+    Elections,
+    Ballots,
+    Votes,
+}
+
+impl Table {
+    const ALL: [Table; 4] = [
+        Table::Guilds,
+        Table::Elections,
+        Table::Ballots,
+        Table::Votes,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Table::Guilds => "guilds",
+            Table::Elections => "elections",
+            Table::Ballots => "ballots",
+            Table::Votes => "votes",
+        }
+    }
+}
+
+pub struct Row {
+    pub table: Table,
+    pub key: String,
+    pub value: serde_json::Value,
+}
+
+/// Guild data that can be stored a row at a time.
+pub trait Rows: Sized {
+    fn to_rows(&self) -> Result<Vec<Row>, anyhow::Error>;
+
+    /// Rebuilds the data from the rows `to_rows` gave.
+    fn from_rows(rows: Vec<Row>) -> Result<Self, anyhow::Error>;
+}
+
+type RowId = (u64, Table, String);
+
+/// Rows to write, and rows to delete with `None`.
+type Batch = Vec<(RowId, Option<String>)>;
+
+/// Work for the writer thread, done in the order it is sent.
+#[derive(Debug)]
+enum Job {
+    Write(Batch),
+    /// Answered once every job sent before it is done.
+    Flush(mpsc::Sender<()>),
+}
+
+#[derive(Debug, Default)]
+struct Saved {
+    /// The JSON of every row as written, or as sent to the writer.
+    rows: BTreeMap<RowId, String>,
+    /// Rows the writer couldn't write, to be sent again whatever they hold.
+    failed: BTreeSet<RowId>,
+}
+
+#[derive(Debug)]
+struct Sqlite {
+    saved: Arc<Mutex<Saved>>,
+    writer: mpsc::Sender<Job>,
+}
+
+fn open(path: &Path) -> Result<Connection, anyhow::Error> {
+    let conn = Connection::open(path).with_context(|| format!("opening {}", path.display()))?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    create_tables(&conn)?;
+    Ok(conn)
+}
+
+fn create_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    for table in Table::ALL {
+        conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                guild_id INTEGER NOT NULL,
+                key TEXT NOT NULL,
+                data TEXT NOT NULL,
+                PRIMARY KEY (guild_id, key)
+            )",
+            table.name()
+        ))?;
+    }
+    Ok(())
+}
+
+/// Every row in the database. Unsealed rows are refused when there is a key, unless `rotating`.
+fn read_rows(
+    conn: &Connection,
+    key: Option<&DataKey>,
+    rotating: bool,
+) -> Result<BTreeMap<RowId, String>, anyhow::Error> {
+    let mut rows = BTreeMap::new();
+    for table in Table::ALL {
+        let mut select =
+            conn.prepare(&format!("SELECT guild_id, key, data FROM {}", table.name()))?;
+        let mut results = select.query([])?;
+        while let Some(row) = results.next()? {
+            let (guild, row_key, data): (i64, String, String) =
+                (row.get(0)?, row.get(1)?, row.get(2)?);
+            let open = if rotating {
+                sealing::open_any
+            } else {
+                sealing::open
+            };
+            let plain = open(data.as_bytes(), key)
+                .with_context(|| format!("opening {} {row_key}", table.name()))?;
+            rows.insert((guild as u64, table, row_key), String::from_utf8(plain)?);
+        }
+    }
+    Ok(rows)
+}
+
+fn write(conn: &mut Connection, key: Option<&DataKey>, batch: &Batch) -> Result<(), anyhow::Error> {
+    let tx = conn.transaction()?;
+    for ((guild, table, row_key), data) in batch {
+        match data {
+            Some(data) => {
+                let data = match key {
+                    Some(key) => String::from_utf8(key.seal(data.as_bytes())?)?,
+                    None => data.clone(),
+                };
+                tx.execute(
+                    &format!(
+                        "INSERT INTO {} (guild_id, key, data) VALUES (?1, ?2, ?3)
+                         ON CONFLICT (guild_id, key) DO UPDATE SET data = excluded.data",
+                        table.name()
+                    ),
+                    params![*guild as i64, row_key, data],
+                )?;
+            }
+            None => {
+                tx.execute(
+                    &format!(
+                        "DELETE FROM {} WHERE guild_id = ?1 AND key = ?2",
+                        table.name()
+                    ),
+                    params![*guild as i64, row_key],
+                )?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Copies the database into `bku/database/` if today's copy hasn't been made, keeping 30.
+fn backup(conn: &Connection, path: &Path) -> Result<(), anyhow::Error> {
+    const KEEP: usize = 30;
+    let folder = Path::new("bku/database");
+    std::fs::create_dir_all(folder)?;
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let day = Utc::now().timestamp() / 60 / 60 / 24;
+    let target = folder.join(format!("{stem}-{day}.db"));
+    if target.exists() {
+        return Ok(());
+    }
+    conn.execute("VACUUM INTO ?1", params![target.to_string_lossy()])?;
+
+    let mut existing: Vec<_> = std::fs::read_dir(folder)?
+        .map(|f| f.map(|f| f.path()))
+        .collect::<Result<_, _>>()?;
+    existing.sort();
+    let count = existing.len();
+    for file in existing.into_iter().take(count.saturating_sub(KEEP)) {
+        std::fs::remove_file(file)?;
+    }
+    Ok(())
+}
+
+impl Sqlite {
+    /// The rows of the `changed` guilds that differ from those saved, and the rows that failed to
+    /// be written, remembering them as saved. Other guilds aren't looked at.
+    fn changes<GuildData: Rows>(
+        saved: &mut Saved,
+        data: &GlobalData<GuildData>,
+        changed: impl IntoIterator<Item = serenity::GuildId>,
+    ) -> Result<Batch, anyhow::Error> {
+        // Every guild is turned into rows before `saved` is touched, so a failure leaves it as it
+        // was.
+        let mut guilds = Vec::new();
+        for guild in changed {
+            let mut rows = BTreeMap::new();
+            if let Some(guild_data) = data.guilds.get(&guild) {
+                for row in guild_data.to_rows()? {
+                    rows.insert(
+                        (guild.get(), row.table, row.key),
+                        serde_json::to_string(&row.value)?,
+                    );
+                }
+            }
+            guilds.push((guild.get(), rows));
+        }
+
+        let mut batch = BTreeMap::new();
+        for id in std::mem::take(&mut saved.failed) {
+            let data = saved.rows.get(&id).cloned();
+            batch.insert(id, data);
+        }
+        for (guild, rows) in guilds {
+            let gone: Vec<RowId> = saved
+                .rows
+                .range((guild, Table::Guilds, String::new())..)
+                .take_while(|(id, _)| id.0 == guild)
+                .filter(|(id, _)| !rows.contains_key(*id))
+                .map(|(id, _)| id.clone())
+                .collect();
+            for id in gone {
+                saved.rows.remove(&id);
+                batch.insert(id, None);
+            }
+            for (id, data) in rows {
+                if saved.rows.get(&id) != Some(&data) {
+                    batch.insert(id.clone(), Some(data.clone()));
+                    saved.rows.insert(id, data);
+                }
+            }
+        }
+        Ok(batch.into_iter().collect())
+    }
+
+    fn saved(&self) -> Result<std::sync::MutexGuard<'_, Saved>, anyhow::Error> {
+        self.saved
+            .lock()
+            .map_err(|_| anyhow::anyhow!("a save panicked"))
+    }
+}
+
+impl<GuildData: Rows> Storage<GuildData> for Sqlite {
+    fn save(
+        &self,
+        data: &GlobalData<GuildData>,
+        changed: &BTreeSet<serenity::GuildId>,
+    ) -> Result<(), anyhow::Error> {
+        let mut saved = self.saved()?;
+        let batch = Sqlite::changes(&mut saved, data, changed.iter().copied())?;
+        if !batch.is_empty() {
+            self.writer
+                .send(Job::Write(batch))
+                .map_err(|_| anyhow::anyhow!("the database writer has stopped"))?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), anyhow::Error> {
+        let (done, finished) = mpsc::channel();
+        self.writer
+            .send(Job::Flush(done))
+            .map_err(|_| anyhow::anyhow!("the database writer has stopped"))?;
+        finished
+            .recv()
+            .map_err(|_| anyhow::anyhow!("the database writer has stopped"))?;
+        match self.saved()?.failed.len() {
+            0 => Ok(()),
+            failed => Err(anyhow::anyhow!("{failed} rows couldn't be written")),
+        }
+    }
+}
+
+/// The data the rows of a database hold.
+fn rebuild<GuildData: Rows>(
+    rows: &BTreeMap<RowId, String>,
+) -> Result<GlobalData<GuildData>, anyhow::Error> {
+    let mut guilds: BTreeMap<u64, Vec<Row>> = BTreeMap::new();
+    for ((guild, table, row_key), data) in rows {
+        guilds.entry(*guild).or_default().push(Row {
+            table: *table,
+            key: row_key.clone(),
+            value: serde_json::from_str(data)?,
+        });
+    }
+    let guilds = guilds
+        .into_iter()
+        .map(|(id, rows)| {
+            let guild =
+                GuildData::from_rows(rows).with_context(|| format!("loading guild {id}"))?;
+            Ok((serenity::GuildId::new(id), guild))
+        })
+        .collect::<Result<_, anyhow::Error>>()?;
+    Ok(GlobalData {
+        guilds,
+        storage: None,
+        changed: BTreeSet::new(),
+    })
+}
+
+impl<GuildData> GlobalData<GuildData> {
+    /// Reads the data from the database at `path` and saves it there from then on, sealing rows
+    /// with `key` if there is one. A new database is filled from `{json_path_base}.json`.
+    pub fn load_sqlite<P: AsRef<Path>>(
+        path: P,
+        json_path_base: &str,
+        key: Option<DataKey>,
+    ) -> Result<Self, anyhow::Error>
+    where
+        GuildData: Rows + serde::de::DeserializeOwned + Default + 'static,
+    {
+        let path = path.as_ref().to_owned();
+        let mut conn = open(&path)?;
+        let mut saved = Saved {
+            rows: read_rows(&conn, key.as_ref(), false)?,
+            failed: BTreeSet::new(),
+        };
+
+        let mut data = if saved.rows.is_empty() {
+            let data: GlobalData<GuildData> = read_json(json_path_base, key.as_ref())?;
+            let batch = Sqlite::changes(&mut saved, &data, data.guilds.keys().copied())?;
+            if !batch.is_empty() {
+                tracing::info!("Copying {json_path_base}.json into {}", path.display());
+            }
+            write(&mut conn, key.as_ref(), &batch)?;
+            data
+        } else {
+            rebuild(&saved.rows)?
+        };
+
+        let saved = Arc::new(Mutex::new(saved));
+        let (writer, jobs) = mpsc::channel();
+        let failed = saved.clone();
+        std::thread::spawn(move || {
+            for job in jobs {
+                let batch = match job {
+                    Job::Write(batch) => batch,
+                    Job::Flush(done) => {
+                        let _ = done.send(());
+                        continue;
+                    }
+                };
+                if let Err(e) = write(&mut conn, key.as_ref(), &batch) {
+                    tracing::error!("Couldn't save to {}: {e:?}", path.display());
+                    if let Ok(mut failed) = failed.lock() {
+                        failed.failed.extend(batch.into_iter().map(|(id, _)| id));
+                    }
+                }
+                if let Err(e) = backup(&conn, &path) {
+                    tracing::error!("Couldn't back up {}: {e:?}", path.display());
+                }
+            }
+        });
+        data.storage = Some(Box::new(Sqlite { saved, writer }));
+        Ok(data)
+    }
+}
+
+/// Re-seals every row of the database at `path` and of its backups with `new`, opening them
+/// with `old`. Returns how many databases were rewritten.
+pub fn rotate_database_key<P: AsRef<Path>>(
+    path: P,
+    old: Option<&DataKey>,
+    new: Option<&DataKey>,
+) -> Result<usize, anyhow::Error> {
+    let mut backups = Vec::new();
+    if let Ok(entries) = std::fs::read_dir("bku/database") {
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_file() && path.extension() == Some("db".as_ref()) {
+                backups.push(path);
+            }
+        }
+    }
+    rotate(path.as_ref(), backups, old, new)
+}
+
+fn rotate(
+    path: &Path,
+    backups: Vec<PathBuf>,
+    old: Option<&DataKey>,
+    new: Option<&DataKey>,
+) -> Result<usize, anyhow::Error> {
+    // Read everything before rewriting anything, so a wrong key leaves the databases as they
+    // were. Backups are only read, never changed in place.
+    let mut opened = Vec::new();
+    if path.is_file() {
+        let conn = open(path)?;
+        let rows = read_rows(&conn, old, true).with_context(|| format!("in {}", path.display()))?;
+        opened.push((path.to_owned(), rows));
+    }
+    for backup in backups {
+        let conn = Connection::open_with_flags(&backup, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("opening {}", backup.display()))?;
+        let rows =
+            read_rows(&conn, old, true).with_context(|| format!("in {}", backup.display()))?;
+        opened.push((backup, rows));
+    }
+    let count = opened.len();
+    for (path, rows) in opened {
+        replace(&path, new, rows).with_context(|| format!("rewriting {}", path.display()))?;
+    }
+    Ok(count)
+}
+
+/// Writes `rows`, sealed with `key` if there is one, to a new database that then takes the place
+/// of the one at `path`.
+fn replace(
+    path: &Path,
+    key: Option<&DataKey>,
+    rows: BTreeMap<RowId, String>,
+) -> Result<(), anyhow::Error> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    match std::fs::remove_file(&temp) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let mut conn = Connection::open(&temp)?;
+    create_tables(&conn)?;
+    write(
+        &mut conn,
+        key,
+        &rows
+            .into_iter()
+            .map(|(id, data)| (id, Some(data)))
+            .collect(),
+    )?;
+    conn.close().map_err(|(_, e)| e)?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+impl<GuildData: Rows> GlobalData<GuildData> {
+    /// Writes the data to an in-memory database and reads it back.
+    pub fn through_database(&self) -> Result<Self, anyhow::Error> {
+        let mut conn = open(Path::new(":memory:"))?;
+        let batch = Sqlite::changes(&mut Saved::default(), self, self.guilds.keys().copied())?;
+        write(&mut conn, None, &batch)?;
+        rebuild(&read_rows(&conn, None, false)?)
+    }
+
+    /// The rows saving the data writes over `before`, and whether each is written or deleted.
+    pub fn changed_rows(&self, before: &Self) -> Result<Vec<(Table, String, bool)>, anyhow::Error> {
+        let mut saved = Saved::default();
+        Sqlite::changes(&mut saved, before, before.guilds.keys().copied())?;
+        Ok(
+            Sqlite::changes(&mut saved, self, self.guilds.keys().copied())?
+                .into_iter()
+                .map(|((_, table, key), data)| (table, key, data.is_some()))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq, serde::Deserialize)]
+    struct Guild {
+        name: String,
+        things: BTreeMap<String, u32>,
+    }
+
+    impl Rows for Guild {
+        fn to_rows(&self) -> Result<Vec<Row>, anyhow::Error> {
+            let mut rows = vec![Row {
+                table: Table::Guilds,
+                key: String::new(),
+                value: self.name.clone().into(),
+            }];
+            rows.extend(self.things.iter().map(|(k, v)| Row {
+                table: Table::Elections,
+                key: k.clone(),
+                value: (*v).into(),
+            }));
+            Ok(rows)
+        }
+
+        fn from_rows(rows: Vec<Row>) -> Result<Self, anyhow::Error> {
+            let mut guild = Guild::default();
+            for row in rows {
+                match row.table {
+                    Table::Guilds => guild.name = serde_json::from_value(row.value)?,
+                    _ => {
+                        guild
+                            .things
+                            .insert(row.key, serde_json::from_value(row.value)?);
+                    }
+                }
+            }
+            Ok(guild)
+        }
+    }
+
+    fn data(things: &[(&str, u32)]) -> GlobalData<Guild> {
+        let guild = Guild {
+            name: "tea".into(),
+            things: things.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+        };
+        GlobalData {
+            guilds: BTreeMap::from([(serenity::GuildId::new(1), guild)]),
+            storage: None,
+            changed: BTreeSet::new(),
+        }
+    }
+
+    #[test]
+    fn test_only_changed_rows_are_written() {
+        let mut saved = Saved::default();
+        let batch = Sqlite::changes(&mut saved, &data(&[("a", 1), ("b", 2)]), [1.into()]).unwrap();
+        assert_eq!(batch.len(), 3);
+
+        let batch = Sqlite::changes(&mut saved, &data(&[("a", 1), ("c", 3)]), [1.into()]).unwrap();
+        assert_eq!(
+            batch,
+            vec![
+                ((1, Table::Elections, "b".into()), None),
+                ((1, Table::Elections, "c".into()), Some("3".into())),
+            ]
+        );
+        assert!(
+            Sqlite::changes(&mut saved, &data(&[("a", 1), ("c", 3)]), [1.into()])
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_only_changed_guilds_are_compared() {
+        let mut saved = Saved::default();
+        Sqlite::changes(&mut saved, &data(&[("a", 1)]), [1.into()]).unwrap();
+        assert!(Sqlite::changes(&mut saved, &data(&[("a", 2)]), [])
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            Sqlite::changes(&mut saved, &data(&[("a", 2)]), [1.into()]).unwrap(),
+            vec![((1, Table::Elections, "a".into()), Some("2".into()))]
+        );
+    }
+
+    #[test]
+    fn test_failed_rows_are_written_again() {
+        let mut saved = Saved::default();
+        Sqlite::changes(&mut saved, &data(&[("a", 1), ("b", 2)]), [1.into()]).unwrap();
+        saved.failed.extend([
+            (1, Table::Elections, "a".into()),
+            (1, Table::Elections, "b".into()),
+        ]);
+
+        // Whatever the database holds after a failed write, the rows are set to the data.
+        let batch = Sqlite::changes(&mut saved, &data(&[("a", 1)]), [1.into()]).unwrap();
+        assert_eq!(
+            batch,
+            vec![
+                ((1, Table::Elections, "a".into()), Some("1".into())),
+                ((1, Table::Elections, "b".into()), None),
+            ]
+        );
+        assert!(Sqlite::changes(&mut saved, &data(&[("a", 1)]), [1.into()])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_rows_survive_a_round_trip() {
+        let key = DataKey::parse(&"ab".repeat(32)).unwrap();
+        let mut conn = open(Path::new(":memory:")).unwrap();
+        let mut saved = Saved::default();
+        let batch = Sqlite::changes(&mut saved, &data(&[("a", 1), ("b", 2)]), [1.into()]).unwrap();
+        write(&mut conn, Some(&key), &batch).unwrap();
+
+        let data: String = conn
+            .query_row("SELECT data FROM elections WHERE key = 'a'", [], |r| {
+                r.get(0)
+            })
+            .unwrap();
+        assert!(data.contains("ciphertext"));
+        assert_eq!(read_rows(&conn, Some(&key), false).unwrap(), saved.rows);
+        assert!(read_rows(&conn, None, false).is_err());
+    }
+
+    #[test]
+    fn test_rotation_only_reads_backups_until_every_key_is_right() {
+        let folder = std::env::temp_dir().join(format!("tea-house-rotate-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        let live = folder.join("live.db");
+        let copy = folder.join("copy.db");
+        let old = DataKey::parse(&"ab".repeat(32)).unwrap();
+        let new = DataKey::parse(&"cd".repeat(32)).unwrap();
+
+        let mut saved = Saved::default();
+        let batch = Sqlite::changes(&mut saved, &data(&[("a", 1)]), [1.into()]).unwrap();
+        let mut conn = open(&live).unwrap();
+        write(&mut conn, Some(&old), &batch).unwrap();
+        conn.execute("VACUUM INTO ?1", params![copy.to_string_lossy()])
+            .unwrap();
+        drop(conn);
+
+        // A wrong key leaves the backup untouched.
+        let before = std::fs::read(&copy).unwrap();
+        assert!(rotate(&live, vec![copy.clone()], Some(&new), None).is_err());
+        assert_eq!(std::fs::read(&copy).unwrap(), before);
+        assert!(!folder.join("copy.db-wal").exists());
+
+        assert_eq!(
+            rotate(&live, vec![copy.clone()], Some(&old), Some(&new)).unwrap(),
+            2
+        );
+        for path in [&live, &copy] {
+            let conn = open(path).unwrap();
+            assert_eq!(read_rows(&conn, Some(&new), false).unwrap(), saved.rows);
+        }
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
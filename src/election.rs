@@ -1,7 +1,32 @@
-use poise::serenity_prelude as serenity;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use poise::{serenity_prelude as serenity, ChoiceParameter as _};
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+mod eligibility;
+mod ledger;
+mod nominations;
+mod outcome;
+mod receipts;
+mod reminders;
+mod roll;
+mod schulze;
+mod seats;
+mod secrecy;
+pub mod stv;
+mod ties;
+
+pub use eligibility::{Eligibility, Voter};
+pub use nominations::{parse_file, parse_lines, parse_list};
+pub use outcome::{Outcome, Standing, Status};
+pub use reminders::parse_reminders;
+pub use roll::parse_roll;
+use seats::Seats;
+pub use seats::{parse_quota, Quota, Seat, VacancyPolicy};
+pub use secrecy::BallotKey;
+use secrecy::SecretBallots;
+pub use ties::{Tie, TieBreak};
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Name(String);
@@ -18,7 +43,7 @@ impl<S: Into<String>> From<S> for Name {
     }
 }
 
-#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Region(String);
 
 impl std::fmt::Display for Region {
@@ -33,13 +58,319 @@ impl<S: Into<String>> From<S> for Region {
     }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+/// The regions a candidate covers. A candidate may cover several regions or none.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "RegionsRepr")]
+pub struct Regions(BTreeSet<Region>);
+
+/// Elections saved before candidates could cover several regions stored a single region.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RegionsRepr {
+    One(Region),
+    Many(BTreeSet<Region>),
+}
+
+impl From<RegionsRepr> for Regions {
+    fn from(value: RegionsRepr) -> Self {
+        match value {
+            RegionsRepr::One(region) => Regions(BTreeSet::from([region])),
+            RegionsRepr::Many(regions) => Regions(regions),
+        }
+    }
+}
+
+impl Regions {
+    pub fn contains(&self, region: &Region) -> bool {
+        self.0.contains(region)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Region> {
+        self.0.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl std::fmt::Display for Regions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "no region");
+        }
+        let regions: Vec<_> = self.0.iter().map(|r| r.to_string()).collect();
+        write!(f, "{}", regions.join("/"))
+    }
+}
+
+/// Parses regions separated by `/`, such as `AMER/EMEA`. An empty string means no region.
+impl<S: Into<String>> From<S> for Regions {
+    fn from(value: S) -> Self {
+        Regions(
+            value
+                .into()
+                .split('/')
+                .map(str::trim)
+                .filter(|r| !r.is_empty())
+                .map(Region::from)
+                .collect(),
+        )
+    }
+}
+
+/// A candidate's regions and the profile voters see when scoring them.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "CandidateRepr")]
+pub struct Candidate {
+    pub regions: Regions,
+    /// The candidate's statement to voters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    statement: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    link: Option<String>,
+    /// The candidate's Discord account, whose avatar is shown to voters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user: Option<serenity::UserId>,
+}
+
+/// Elections saved before candidates had profiles stored only their regions.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CandidateRepr {
+    Regions(Regions),
+    Profile {
+        regions: Regions,
+        #[serde(default)]
+        statement: Option<String>,
+        #[serde(default)]
+        link: Option<String>,
+        #[serde(default)]
+        user: Option<serenity::UserId>,
+    },
+}
+
+impl From<CandidateRepr> for Candidate {
+    fn from(value: CandidateRepr) -> Self {
+        match value {
+            CandidateRepr::Regions(regions) => regions.into(),
+            CandidateRepr::Profile {
+                regions,
+                statement,
+                link,
+                user,
+            } => Candidate {
+                regions,
+                statement,
+                link,
+                user,
+            },
+        }
+    }
+}
+
+impl From<Regions> for Candidate {
+    fn from(regions: Regions) -> Self {
+        Candidate {
+            regions,
+            ..Default::default()
+        }
+    }
+}
+
+impl Candidate {
+    /// Long enough for a short statement and well within Discord's embed limits.
+    const MAX_STATEMENT: usize = 2000;
+
+    pub fn user(&self) -> Option<serenity::UserId> {
+        self.user
+    }
+
+    /// Replaces the candidate's profile. Links must be web addresses.
+    pub fn set_profile(
+        &mut self,
+        statement: Option<String>,
+        link: Option<String>,
+        user: Option<serenity::UserId>,
+    ) -> Result<(), anyhow::Error> {
+        if statement
+            .as_ref()
+            .is_some_and(|s| s.chars().count() > Candidate::MAX_STATEMENT)
+        {
+            anyhow::bail!(
+                "Statements can be at most {} characters",
+                Candidate::MAX_STATEMENT
+            );
+        }
+        if let Some(link) = &link {
+            if !link.starts_with("https://") && !link.starts_with("http://") {
+                anyhow::bail!("Links must start with https:// or http://");
+            }
+        }
+        self.statement = statement;
+        self.link = link;
+        self.user = user;
+        Ok(())
+    }
+
+    /// The prompt shown while a voter scores this candidate. `avatar` is the image URL of the
+    /// candidate's Discord account, if they have one.
+    pub fn make_embed(&self, name: &Name, avatar: Option<String>) -> serenity::CreateEmbed {
+        let mut embed = serenity::CreateEmbed::new()
+            .title(name.to_string())
+            .color(serenity::Color::BLURPLE)
+            .field("Regions", self.regions.to_string(), true);
+        if let Some(user) = self.user {
+            embed = embed.field("Discord", format!("<@{user}>"), true);
+        }
+        if let Some(statement) = &self.statement {
+            embed = embed.description(statement);
+        }
+        if let Some(link) = &self.link {
+            embed = embed.url(link);
+        }
+        if let Some(avatar) = avatar {
+            embed = embed.thumbnail(avatar);
+        }
+        embed
+    }
+}
+
+/// What voters are asked for each candidate.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, poise::ChoiceParameter,
+)]
+pub enum ElectionKind {
+    /// Each candidate is scored from 1 to 5, or skipped.
+    #[default]
+    #[name = "Score"]
+    Score,
+
+    /// Each candidate is approved (recorded as 1) or rejected (recorded as 0).
+    #[name = "Approval"]
+    Approval,
+}
+
+/// The range of scores voters can give on a score ballot.
+///
+/// Ballots record the position on the scale, starting at 1, so that 0 always means the voter
+/// abstained even when the lowest score on the scale is 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Scale {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Scale { min: 1, max: 5 }
+    }
+}
+
+impl Scale {
+    /// Discord select menus are limited to 25 options.
+    const MAX_OPTIONS: usize = 25;
+
+    /// The score a recorded rank stands for, or `None` for an abstention.
+    pub fn score(&self, rank: usize) -> Option<usize> {
+        (rank > 0).then(|| self.min + rank - 1)
+    }
+
+    /// Every `(label, rank)` pair a voter can choose from.
+    pub fn options(&self) -> impl Iterator<Item = (String, usize)> + '_ {
+        (self.min..=self.max).enumerate().map(|(i, score)| {
+            let label = if score == self.min {
+                format!("{score} (least desired)")
+            } else if score == self.max {
+                format!("{score} (most desired)")
+            } else {
+                format!("{score}")
+            };
+            (label, i + 1)
+        })
+    }
+}
+
+impl std::fmt::Display for Scale {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}–{}", self.min, self.max)
+    }
+}
+
+impl std::str::FromStr for Scale {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (min, max) = s
+            .split_once('-')
+            .ok_or_else(|| anyhow::anyhow!("Scale {s} should look like 1-5"))?;
+        let scale = Scale {
+            min: min.trim().parse()?,
+            max: max.trim().parse()?,
+        };
+        if scale.max <= scale.min {
+            anyhow::bail!("The top of the scale must be above the bottom");
+        }
+        if scale.max - scale.min + 1 > Scale::MAX_OPTIONS {
+            anyhow::bail!("A scale can have at most {} options", Scale::MAX_OPTIONS);
+        }
+        Ok(scale)
+    }
+}
+
+/// How much support a candidate needs before they can be elected.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Support {
+    /// At least this many voters must have scored or approved the candidate.
+    Votes(usize),
+    /// At least this percentage of ballots must have scored or approved the candidate.
+    Percent(f32),
+}
+
+impl Support {
+    /// The number of supporting votes needed out of `ballots`.
+    fn required(&self, ballots: usize) -> usize {
+        match self {
+            Support::Votes(votes) => *votes,
+            Support::Percent(percent) => (ballots as f32 * percent / 100.).ceil() as usize,
+        }
+    }
+}
+
+impl std::fmt::Display for Support {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Support::Votes(votes) => write!(f, "{votes} votes"),
+            Support::Percent(percent) => write!(f, "{percent}% of ballots"),
+        }
+    }
+}
+
+impl std::str::FromStr for Support {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.strip_suffix('%') {
+            Some(percent) => {
+                let percent: f32 = percent.trim().parse()?;
+                if !(0. ..=100.).contains(&percent) {
+                    anyhow::bail!("Minimum support must be between 0% and 100%");
+                }
+                Ok(Support::Percent(percent))
+            }
+            None => Ok(Support::Votes(s.parse()?)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Ballot {
     pub votes: BTreeMap<Name, usize>,
 }
 
 impl Ballot {
-    pub fn make_embed(&self) -> serenity::CreateEmbed {
+    pub fn make_embed(&self, election: &Election) -> serenity::CreateEmbed {
         let embed = serenity::CreateEmbed::new()
             .title("Your current ballot")
             .color(serenity::Color::DARK_GREEN)
@@ -47,12 +378,13 @@ impl Ballot {
                 "Votes",
                 self.votes
                     .iter()
-                    .map(|(n, r)| {
-                        if *r > 0 {
-                            format!("* **{n}**: {r}")
-                        } else {
-                            format!("* **{n}**: Abstained")
-                        }
+                    .map(|(n, r)| match election.kind {
+                        ElectionKind::Score => match election.scale.score(*r) {
+                            Some(score) => format!("* **{n}**: {score}"),
+                            None => format!("* **{n}**: Abstained"),
+                        },
+                        ElectionKind::Approval if *r > 0 => format!("* **{n}**: Approved"),
+                        ElectionKind::Approval => format!("* **{n}**: Rejected"),
                     })
                     .collect::<Vec<_>>()
                     .join("\n"),
@@ -62,13 +394,164 @@ impl Ballot {
     }
 }
 
+/// How ballots are turned into a set of elected candidates.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, poise::ChoiceParameter,
+)]
+pub enum CountingMethod {
+    /// Average each candidate's non-abstaining scores, then fill offices greedily.
+    #[default]
+    #[name = "Score"]
+    Score,
+
+    /// Single Transferable Vote using the Droop quota and fractional surplus transfers.
+    #[name = "Single Transferable Vote"]
+    Stv,
+
+    /// Schulze pairwise comparison, then fill offices greedily.
+    #[name = "Schulze (Condorcet)"]
+    Schulze,
+
+    /// Convert each ballot into positional points, then fill offices greedily.
+    #[name = "Borda count"]
+    Borda,
+}
+
+/// Where an election is in its lifecycle.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum State {
+    /// Waiting for its opening time.
+    Draft,
+
+    /// Accepting ballots. Elections from before the lifecycle was tracked are open.
+    #[default]
+    Open,
+
+    /// No longer accepting ballots.
+    Closed,
+
+    /// The owner has confirmed the results.
+    Certified,
+}
+
+impl std::fmt::Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            State::Draft => write!(f, "Draft"),
+            State::Open => write!(f, "Open"),
+            State::Closed => write!(f, "Closed"),
+            State::Certified => write!(f, "Certified"),
+        }
+    }
+}
+
+/// Parses a UTC time such as `2024-06-01 18:00` or an RFC 3339 timestamp.
+pub fn parse_time(s: &str) -> Result<DateTime<Utc>, anyhow::Error> {
+    let s = s.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.to_utc());
+    }
+    let time = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M")
+        .map_err(|_| anyhow::anyhow!("{s} should look like 2024-06-01 18:00 (UTC)"))?;
+    Ok(time.and_utc())
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Election {
     owner: serenity::UserId,
-    pub candidates: BTreeMap<Name, Region>,
+    pub candidates: BTreeMap<Name, Candidate>,
     offices: usize,
-    reserved_offices: Vec<Region>,
+    #[serde(default)]
+    quotas: BTreeMap<Region, Quota>,
+    /// One entry per reserved office, from before quotas. Folded into `quotas` by `migrate`.
+    #[serde(default, rename = "reserved_offices", skip_serializing)]
+    legacy_reserved_offices: Vec<Region>,
+    #[serde(default)]
+    vacancy_policy: VacancyPolicy,
+    /// Ballots by voter. Empty in secret elections.
     pub ballots: BTreeMap<serenity::UserId, Ballot>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    secret: Option<SecretBallots>,
+    /// Every change to the ballots, chained by hash.
+    #[serde(default)]
+    log: Vec<ledger::Entry>,
+
+    #[serde(default)]
+    method: CountingMethod,
+
+    #[serde(default)]
+    kind: ElectionKind,
+
+    #[serde(default)]
+    scale: Scale,
+
+    #[serde(default)]
+    min_support: Option<Support>,
+
+    /// Shrink average scores toward the election mean so that candidates with few votes can't
+    /// ride a handful of high scores.
+    #[serde(default)]
+    bayesian: bool,
+
+    #[serde(default)]
+    tie_break: TieBreak,
+    /// Seeds the tie-breaking draw. Sealed when voting closes, before any draw is made, and
+    /// published with the results so that anyone can reproduce them.
+    #[serde(default)]
+    tie_seed: Option<u64>,
+    /// Candidates in the order they were nominated.
+    #[serde(default)]
+    nominated: Vec<Name>,
+    /// Tie-break order drawn by the owner, best placed first.
+    #[serde(default)]
+    lots: Vec<Name>,
+
+    #[serde(default)]
+    state: State,
+    #[serde(default)]
+    opens_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    closes_at: Option<DateTime<Utc>>,
+    /// The message announcing the election, so it can be refreshed when the state changes.
+    #[serde(default)]
+    message: Option<(serenity::ChannelId, serenity::MessageId)>,
+    /// Where the winners are posted when voting closes.
+    #[serde(default)]
+    announce_in: Option<serenity::ChannelId>,
+    /// Candidates who stood down after voting started. They stay on the ballot record but can't
+    /// be elected.
+    #[serde(default)]
+    withdrawn: BTreeSet<Name>,
+    /// Whether candidates with a Discord account are kept off their own ballot.
+    #[serde(default)]
+    forbid_self_votes: bool,
+    /// Given to winners with a Discord account when the results are certified.
+    #[serde(default)]
+    winner_role: Option<serenity::RoleId>,
+    /// Whether members holding the winner role who weren't elected lose it on certification.
+    #[serde(default)]
+    replace_role_holders: bool,
+    #[serde(default)]
+    eligibility: Eligibility,
+    /// The fixed electorate, if the election has one.
+    #[serde(default)]
+    roll: Option<BTreeSet<serenity::UserId>>,
+    /// A role whose members become the roll when voting opens.
+    #[serde(default)]
+    roll_role: Option<serenity::RoleId>,
+    /// Hours before closing to remind members of the roll who haven't voted.
+    #[serde(default)]
+    reminders: BTreeSet<u32>,
+    /// Reminders already sent, or skipped because their time had passed.
+    #[serde(default)]
+    reminded: BTreeSet<u32>,
+    /// Whether voting closed on schedule and the results haven't been posted to `announce_in`
+    /// yet.
+    #[serde(default)]
+    announcement_pending: bool,
+    /// Whether the results have been posted to `announce_in`.
+    #[serde(default)]
+    announced: bool,
 }
 
 impl Election {
@@ -78,8 +561,37 @@ impl Election {
             offices,
 
             candidates: BTreeMap::new(),
-            reserved_offices: Vec::new(),
+            quotas: BTreeMap::new(),
+            legacy_reserved_offices: Vec::new(),
+            vacancy_policy: VacancyPolicy::default(),
             ballots: BTreeMap::new(),
+            secret: None,
+            log: Vec::new(),
+            method: CountingMethod::default(),
+            kind: ElectionKind::default(),
+            scale: Scale::default(),
+            min_support: None,
+            bayesian: false,
+            tie_break: TieBreak::default(),
+            tie_seed: None,
+            nominated: Vec::new(),
+            lots: Vec::new(),
+            state: State::default(),
+            opens_at: None,
+            closes_at: None,
+            message: None,
+            announce_in: None,
+            withdrawn: BTreeSet::new(),
+            forbid_self_votes: false,
+            winner_role: None,
+            replace_role_holders: false,
+            eligibility: Eligibility::default(),
+            roll: None,
+            roll_role: None,
+            reminders: BTreeSet::new(),
+            reminded: BTreeSet::new(),
+            announcement_pending: false,
+            announced: false,
         }
     }
 
@@ -87,51 +599,512 @@ impl Election {
         &self.owner
     }
 
+    pub fn migrate(&mut self) {
+        for region in std::mem::take(&mut self.legacy_reserved_offices) {
+            self.quotas.entry(region).or_default().min += 1;
+        }
+        self.import_ballots(Utc::now());
+        // Elections that closed before seeds were sealed on closing.
+        if matches!(self.state, State::Closed | State::Certified) {
+            self.seal_seed();
+        }
+    }
+
+    pub fn method(&self) -> CountingMethod {
+        self.method
+    }
+
+    pub fn set_method(&mut self, method: CountingMethod) {
+        self.method = method;
+    }
+
+    pub fn kind(&self) -> ElectionKind {
+        self.kind
+    }
+
+    pub fn set_kind(&mut self, kind: ElectionKind) {
+        self.kind = kind;
+    }
+
+    pub fn scale(&self) -> Scale {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: Scale) {
+        self.scale = scale;
+    }
+
+    pub fn set_min_support(&mut self, min_support: Option<Support>) {
+        self.min_support = min_support;
+    }
+
+    pub fn set_bayesian(&mut self, bayesian: bool) {
+        self.bayesian = bayesian;
+    }
+
+    pub fn set_tie_break(&mut self, tie_break: TieBreak) {
+        self.tie_break = tie_break;
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn closes_at(&self) -> Option<DateTime<Utc>> {
+        self.closes_at
+    }
+
+    /// Sets when voting opens and closes. An election that opens in the future starts as a draft.
+    pub fn set_schedule(
+        &mut self,
+        opens_at: Option<DateTime<Utc>>,
+        closes_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(closes_at) = closes_at {
+            if closes_at <= opens_at.unwrap_or(now).max(now) {
+                anyhow::bail!("Voting must close after it opens and after now");
+            }
+        }
+        self.opens_at = opens_at;
+        self.closes_at = closes_at;
+        self.state = match opens_at {
+            Some(opens_at) if opens_at > now => State::Draft,
+            _ => State::Open,
+        };
+        self.reset_reminders(now);
+        Ok(())
+    }
+
+    /// Moves the election along its schedule. Returns whether the state changed.
+    pub fn advance(&mut self, now: DateTime<Utc>) -> bool {
+        let before = self.state;
+        if self.state == State::Draft && self.opens_at.is_none_or(|t| t <= now) {
+            self.state = State::Open;
+        }
+        if self.state == State::Open && self.closes_at.is_some_and(|t| t <= now) {
+            self.close();
+            self.announcement_pending = self.announce_in.is_some();
+        }
+        self.state != before
+    }
+
+    /// Whether the results are waiting to be posted after a scheduled close.
+    pub fn announcement_pending(&self) -> bool {
+        self.announcement_pending
+    }
+
+    /// Records that the results were posted.
+    pub fn set_announced(&mut self) {
+        self.announcement_pending = false;
+        self.announced = true;
+    }
+
+    /// Whether the owner may post the results to the announcement channel: only once they are
+    /// certified, and only once.
+    pub fn check_announce(&self) -> Result<(), anyhow::Error> {
+        if self.state != State::Certified {
+            anyhow::bail!("Results can only be announced once they are certified.");
+        }
+        if self.announced {
+            anyhow::bail!("The results have already been announced.");
+        }
+        Ok(())
+    }
+
+    /// Stops accepting ballots and fixes the tie-break seed so the published results can't
+    /// change. Returns false if voting had already ended.
+    pub fn close(&mut self) -> bool {
+        match self.state {
+            State::Draft | State::Open => {
+                self.seal_seed();
+                self.state = State::Closed;
+                true
+            }
+            State::Closed | State::Certified => false,
+        }
+    }
+
+    /// Accepts ballots again after an election was closed, until `closes_at` if given.
+    pub fn reopen(
+        &mut self,
+        closes_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Result<(), anyhow::Error> {
+        if self.state != State::Closed {
+            anyhow::bail!("Only closed elections can be reopened");
+        }
+        if closes_at.is_some_and(|t| t <= now) {
+            anyhow::bail!("Voting must close after now");
+        }
+        self.closes_at = closes_at;
+        self.state = State::Open;
+        self.announcement_pending = false;
+        self.announced = false;
+        self.reset_reminders(now);
+        Ok(())
+    }
+
+    /// Confirms the results of a closed election. Fails unless voting has closed and every office
+    /// that wasn't left vacant was filled.
+    pub fn certify(&mut self) -> Result<(), anyhow::Error> {
+        if self.state != State::Closed {
+            anyhow::bail!("Only closed elections can be certified.");
+        }
+        if self.outcome().elected().is_none() {
+            anyhow::bail!("The results are incomplete, so they can't be certified.");
+        }
+        self.state = State::Certified;
+        Ok(())
+    }
+
+    pub fn message(&self) -> Option<(serenity::ChannelId, serenity::MessageId)> {
+        self.message
+    }
+
+    pub fn set_message(&mut self, channel: serenity::ChannelId, message: serenity::MessageId) {
+        self.message = Some((channel, message));
+    }
+
+    pub fn announce_in(&self) -> Option<serenity::ChannelId> {
+        self.announce_in
+    }
+
+    pub fn set_announce_in(&mut self, channel: Option<serenity::ChannelId>) {
+        self.announce_in = channel;
+    }
+
+    pub fn set_forbid_self_votes(&mut self, forbid: bool) {
+        self.forbid_self_votes = forbid;
+    }
+
+    pub fn winner_role(&self) -> Option<serenity::RoleId> {
+        self.winner_role
+    }
+
+    pub fn set_winner_role(&mut self, role: Option<serenity::RoleId>) {
+        self.winner_role = role;
+    }
+
+    pub fn replace_role_holders(&self) -> bool {
+        self.replace_role_holders
+    }
+
+    pub fn set_replace_role_holders(&mut self, replace: bool) {
+        self.replace_role_holders = replace;
+    }
+
+    pub fn eligibility(&self) -> &Eligibility {
+        &self.eligibility
+    }
+
+    pub fn set_eligibility(&mut self, eligibility: Eligibility) {
+        self.eligibility = eligibility;
+    }
+
+    /// Every reason `user` can't vote, explained to them. Empty if they may vote.
+    pub fn voting_problems(
+        &self,
+        user: serenity::UserId,
+        voter: &Voter,
+        now: DateTime<Utc>,
+    ) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !self.on_roll(user) {
+            problems.push("You aren't on the voter roll".into());
+        }
+        problems.extend(self.eligibility.problems(voter, now));
+        problems
+    }
+
+    /// Fixes the tie-break seed if it hasn't been already and returns it.
+    pub fn seal_seed(&mut self) -> u64 {
+        *self
+            .tie_seed
+            .get_or_insert_with(|| rand::thread_rng().gen())
+    }
+
+    /// Records the owner's lot. Every name must be a candidate.
+    pub fn set_lots(&mut self, lots: Vec<Name>) -> bool {
+        if lots.iter().any(|n| !self.candidates.contains_key(n)) {
+            return false;
+        }
+        self.lots = lots;
+        true
+    }
+
     pub fn make_embed(&self) -> serenity::CreateEmbed {
         let mut embed = serenity::CreateEmbed::new()
             .title("The TEA House Moderator Election")
-            .color(serenity::Color::BLURPLE)
+            .color(match self.state {
+                State::Draft | State::Open => serenity::Color::BLURPLE,
+                State::Closed | State::Certified => serenity::Color::DARK_GREY,
+            })
+            .field(
+                "Status",
+                match (self.state, self.opens_at, self.closes_at) {
+                    (State::Draft, Some(t), _) => format!("Draft, opens <t:{}:R>", t.timestamp()),
+                    (State::Open, _, Some(t)) => format!("Open, closes <t:{}:R>", t.timestamp()),
+                    (state, _, _) => state.to_string(),
+                },
+                true,
+            )
             .field("Offices", format!("{}", self.offices), true)
+            .field(
+                "Ballot",
+                match self.kind {
+                    ElectionKind::Score => format!("Score {}", self.scale),
+                    ElectionKind::Approval => self.kind.name().into(),
+                },
+                true,
+            )
+            .field("Counting method", self.method.name(), true)
             .field(
                 "Candidates",
                 self.candidates
                     .iter()
-                    .map(|(n, r)| format!("* {n} (Region {r})"))
+                    .map(|(n, c)| {
+                        if self.withdrawn.contains(n) {
+                            format!("* ~~{n}~~ (withdrawn)")
+                        } else if let Some(user) = c.user() {
+                            format!("* {n} <@{user}> (Region {})", c.regions)
+                        } else {
+                            format!("* {n} (Region {})", c.regions)
+                        }
+                    })
                     .collect::<Vec<_>>()
                     .join("\n"),
                 false,
             );
 
-        if !self.reserved_offices.is_empty() {
+        if let Some(support) = self.min_support {
+            embed = embed.field("Minimum support", format!("{support}"), true);
+        }
+
+        if let Some(rules) = self.eligibility.describe() {
+            embed = embed.field("Who can vote", rules, false);
+        }
+
+        if self.forbid_self_votes {
+            embed = embed.field("Self votes", "Candidates can't vote for themselves", true);
+        }
+
+        if let Some(role) = self.winner_role {
+            let value = if self.replace_role_holders {
+                format!("<@&{role}>, replacing its holders")
+            } else {
+                format!("<@&{role}>")
+            };
+            embed = embed.field("Winners receive", value, true);
+        }
+
+        if !self.quotas.is_empty() {
             embed = embed.field(
-                "Reserved offices",
-                self.reserved_offices
+                "Regional quotas",
+                self.quotas
                     .iter()
-                    .map(|v| format!("* {v}"))
+                    .map(|(r, q)| format!("* {r}: {q}"))
                     .collect::<Vec<_>>()
                     .join("\n"),
                 false,
             );
+            embed = embed.field("Unfilled reservations", self.vacancy_policy.name(), true);
+        }
+
+        if self.is_secret() {
+            embed = embed.field("Ballots", "Secret", true);
         }
 
-        if !self.ballots.is_empty() {
-            embed = embed.field("Voters", format!("{}", self.ballots.len()), true);
+        if self.ballot_count() > 0 || self.roll.is_some() {
+            embed = embed.field("Turnout", self.turnout(), true);
+        }
+
+        if !self.reminders.is_empty() {
+            let hours: Vec<_> = self
+                .reminders
+                .iter()
+                .rev()
+                .map(|h| format!("{h}h"))
+                .collect();
+            embed = embed.field(
+                "Reminders",
+                format!("{} before closing", hours.join(", ")),
+                true,
+            );
+        }
+
+        if let (None, Some(role)) = (&self.roll, self.roll_role) {
+            embed = embed.field("Voter roll", format!("<@&{role}> when voting opens"), true);
         }
 
         embed
     }
 
+    #[allow(unused)]
     pub fn reserve_office<R: Into<Region>>(&mut self, region: R) -> bool {
-        if self.reserved_offices.len() + 1 > self.offices {
-            false
+        let region = region.into();
+        let mut quota = self.quotas.get(&region).copied().unwrap_or_default();
+        quota.min += 1;
+        quota.max = quota.max.map(|max| max.max(quota.min));
+        self.set_quota(region, quota).is_ok()
+    }
+
+    pub fn set_vacancy_policy(&mut self, policy: VacancyPolicy) {
+        self.vacancy_policy = policy;
+    }
+
+    /// Sets how many offices a region must and may hold.
+    pub fn set_quota<R: Into<Region>>(
+        &mut self,
+        region: R,
+        quota: Quota,
+    ) -> Result<(), anyhow::Error> {
+        let region = region.into();
+        if quota.max.is_some_and(|max| max < quota.min) {
+            anyhow::bail!("{region} can't reserve more offices than its maximum");
+        }
+        let reserved: usize = self
+            .quotas
+            .iter()
+            .filter(|(r, _)| **r != region)
+            .map(|(_, q)| q.min)
+            .sum();
+        if reserved + quota.min > self.offices {
+            anyhow::bail!(
+                "Only {} offices are available but {} are reserved",
+                self.offices,
+                reserved + quota.min
+            );
+        }
+        if quota == Quota::default() {
+            self.quotas.remove(&region);
         } else {
-            self.reserved_offices.push(region.into());
-            true
+            self.quotas.insert(region, quota);
         }
+        Ok(())
     }
 
-    pub fn add_candidate<N: Into<Name>, R: Into<Region>>(&mut self, name: N, region: R) {
-        self.candidates.insert(name.into(), region.into());
+    pub fn set_offices(&mut self, offices: usize) -> Result<(), anyhow::Error> {
+        if offices == 0 {
+            anyhow::bail!("An election needs at least one office");
+        }
+        let reserved: usize = self.quotas.values().map(|q| q.min).sum();
+        if reserved > offices {
+            anyhow::bail!("{reserved} offices are reserved, so there must be at least {reserved}");
+        }
+        self.offices = offices;
+        Ok(())
+    }
+
+    /// Removes a candidate and every vote cast for them. Returns false if there was no such
+    /// candidate.
+    pub fn remove_candidate(&mut self, name: &Name, now: DateTime<Utc>) -> bool {
+        if self.candidates.remove(name).is_none() {
+            return false;
+        }
+        self.nominated.retain(|n| n != name);
+        self.lots.retain(|n| n != name);
+        self.withdrawn.remove(name);
+        for ballot in self.cast_ballots_mut() {
+            ballot.votes.remove(name);
+        }
+        if !self.log.is_empty() {
+            self.record(ledger::Event::CandidateRemoved { name: name.clone() }, now);
+        }
+        true
+    }
+
+    /// Keeps a candidate's votes on record but stops them from being elected. Returns false if
+    /// there was no such candidate.
+    pub fn withdraw_candidate(&mut self, name: &Name) -> bool {
+        self.candidates.contains_key(name) && self.withdrawn.insert(name.clone())
+    }
+
+    /// Candidates `voter` is asked about.
+    pub fn ballot_candidates(
+        &self,
+        voter: serenity::UserId,
+    ) -> impl Iterator<Item = (&Name, &Candidate)> {
+        self.candidates.iter().filter(move |(n, c)| {
+            let own = self.forbid_self_votes && c.user() == Some(voter);
+            !self.withdrawn.contains(*n) && !own
+        })
+    }
+
+    /// The candidate's name in bold, followed by a mention of their Discord account if they have
+    /// one.
+    pub fn mention(&self, name: &Name) -> String {
+        match self.candidates.get(name).and_then(|c| c.user()) {
+            Some(user) => format!("**{name}** (<@{user}>)"),
+            None => format!("**{name}**"),
+        }
+    }
+
+    /// Discord accounts of the elected candidates, if the election completed.
+    pub fn winners(&self) -> Vec<serenity::UserId> {
+        self.outcome()
+            .elected()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|n| self.candidates.get(n).and_then(|c| c.user()))
+            .collect()
+    }
+
+    /// Which of the winner role's `holders` lose it on certification. Nobody does unless the
+    /// election replaces role holders and has winners to hand the role to.
+    pub fn outgoing_holders(
+        &self,
+        holders: impl IntoIterator<Item = serenity::UserId>,
+    ) -> Vec<serenity::UserId> {
+        let winners = self.winners();
+        if !self.replace_role_holders || winners.is_empty() {
+            return Vec::new();
+        }
+        holders
+            .into_iter()
+            .filter(|u| !winners.contains(u))
+            .collect()
+    }
+
+    /// Adds a candidate. Returns false, leaving the election unchanged, if a candidate already has
+    /// the name, ignoring case.
+    pub fn add_candidate<N: Into<Name>, R: Into<Regions>>(&mut self, name: N, regions: R) -> bool {
+        let name = name.into();
+        if self.is_candidate(&name) {
+            return false;
+        }
+        if !self.nominated.contains(&name) {
+            self.nominated.push(name.clone());
+        }
+        self.candidates
+            .insert(name, Candidate::from(regions.into()));
+        true
+    }
+
+    /// Replaces a candidate's statement, link and Discord account.
+    pub fn set_profile(
+        &mut self,
+        name: &Name,
+        statement: Option<String>,
+        link: Option<String>,
+        user: Option<serenity::UserId>,
+    ) -> Result<(), anyhow::Error> {
+        if let Some((other, _)) = user.and_then(|user| {
+            self.candidates
+                .iter()
+                .find(|(n, c)| *n != name && c.user() == Some(user))
+        }) {
+            anyhow::bail!("That account already belongs to {other}");
+        }
+        self.candidates
+            .get_mut(name)
+            .ok_or_else(|| anyhow::anyhow!("{name} is not a candidate"))?
+            .set_profile(statement, link, user)
+    }
+
+    fn is_candidate(&self, name: &Name) -> bool {
+        let name = name.0.to_lowercase();
+        self.candidates.keys().any(|n| n.0.to_lowercase() == name)
     }
 
     #[allow(unused)]
@@ -143,65 +1116,198 @@ impl Election {
             .insert(name.into(), rank);
     }
 
-    fn tally(&self) -> Vec<(f32, Name)> {
-        let mut rng = rand::thread_rng();
+    /// Number of voters who scored or approved each candidate.
+    fn support(&self) -> HashMap<Name, usize> {
+        let mut support: HashMap<Name, usize> =
+            self.candidates.keys().map(|n| (n.clone(), 0)).collect();
+        for ballot in self.cast_ballots() {
+            for (name, rank) in &ballot.votes {
+                if let Some(votes) = support.get_mut(name).filter(|_| *rank > 0) {
+                    *votes += 1;
+                }
+            }
+        }
+        support
+    }
 
+    /// Candidates who can't be elected, because they withdrew or don't have enough support.
+    pub fn ineligible(&self) -> BTreeSet<Name> {
+        let mut ineligible = self.withdrawn.clone();
+        if let Some(min_support) = self.min_support {
+            let required = min_support.required(self.ballot_count());
+            ineligible.extend(
+                self.support()
+                    .into_iter()
+                    .filter(|(_, votes)| *votes < required)
+                    .map(|(n, _)| n),
+            );
+        }
+        ineligible
+    }
+
+    /// Why an ineligible candidate can't be elected.
+    fn ineligible_status(&self, name: &Name) -> Status {
+        if self.withdrawn.contains(name) {
+            Status::Withdrawn
+        } else {
+            Status::Ineligible
+        }
+    }
+
+    fn tally(&self) -> Vec<(f32, Name)> {
         // Track the count of non-zero votes so that the total score can be normalized.
         let mut votes = HashMap::<Name, usize>::new();
         let mut results = HashMap::<Name, usize>::new();
-        for ballot in self.ballots.values() {
+        for ballot in self.cast_ballots() {
             for (name, rank) in &ballot.votes {
-                *results.entry(name.clone()).or_default() += rank;
-                *votes.entry(name.clone()).or_default() += if *rank > 0 { 1 } else { 0 };
+                let score = self.scale.score(*rank);
+                *results.entry(name.clone()).or_default() += score.unwrap_or(0);
+                *votes.entry(name.clone()).or_default() += if score.is_some() { 1 } else { 0 };
             }
         }
-        let mut results: Vec<_> = results
+        // With a Bayesian average every candidate starts with `weight` votes at the mean score.
+        let total_votes: usize = votes.values().sum();
+        let prior = (self.bayesian && total_votes > 0).then(|| {
+            let total_score: usize = results.values().sum();
+            let weight = total_votes as f32 / votes.len() as f32;
+            (weight, total_score as f32 / total_votes as f32)
+        });
+        results
             .into_iter()
             .map(|(n, v)| {
                 let num_votes = *votes.get(&n).unwrap_or(&0);
-                // Normalize the score for this candidate.
-                (v as f32 / num_votes as f32, n)
+                match (self.kind, prior) {
+                    (ElectionKind::Score, Some((weight, mean))) => {
+                        ((weight * mean + v as f32) / (weight + num_votes as f32), n)
+                    }
+                    // A candidate everyone abstained on scores nothing rather than 0/0, which
+                    // would sort differently from platform to platform.
+                    (ElectionKind::Score, None) if num_votes == 0 => (0., n),
+                    // Normalize the score for this candidate.
+                    (ElectionKind::Score, None) => (v as f32 / num_votes as f32, n),
+                    (ElectionKind::Approval, _) => (num_votes as f32, n),
+                }
             })
-            .collect();
-        results.shuffle(&mut rng);
-        results.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            .collect()
+    }
 
-        results
+    /// Scores candidates by positional points.
+    ///
+    /// On each ballot a candidate earns a point for every candidate scored below them and half a
+    /// point for every other candidate sharing their score. Abstentions earn nothing and count as
+    /// the lowest position.
+    fn borda(&self) -> Vec<(f32, Name)> {
+        let mut results: HashMap<Name, f32> =
+            self.candidates.keys().map(|n| (n.clone(), 0.)).collect();
+        for ballot in self.cast_ballots() {
+            let rank = |n: &Name| ballot.votes.get(n).copied().unwrap_or(0);
+            for (name, points) in results.iter_mut() {
+                let own = rank(name);
+                if own == 0 {
+                    continue;
+                }
+                for other in self.candidates.keys().filter(|o| *o != name) {
+                    let theirs = rank(other);
+                    if theirs < own {
+                        *points += 1.;
+                    } else if theirs == own {
+                        *points += 0.5;
+                    }
+                }
+            }
+        }
+        results.into_iter().map(|(n, p)| (p, n)).collect()
     }
 
-    fn assign(&self, mut results: Vec<(f32, Name)>) -> Option<Vec<Name>> {
-        let mut reserved_offices = self.reserved_offices.clone();
-        let mut unreserved = self.offices - self.reserved_offices.len();
-        let mut officers = Vec::new();
-
-        while officers.len() < self.offices {
-            let (_, candidate) = results.pop()?;
-            tracing::info!("assigning {candidate} {officers:?}({})", self.offices);
-            let region = self.candidates.get(&candidate).unwrap();
-
-            if let Some(ix) = reserved_offices.iter().position(|x| x == region) {
-                officers.push(candidate.clone());
-                reserved_offices.remove(ix);
-                tracing::warn!(
-                    "{candidate} takes reserved office {region} ({})",
-                    reserved_offices.len()
-                );
-            } else if unreserved > 0 {
-                officers.push(candidate.clone());
-                unreserved -= 1;
-                tracing::warn!("{candidate} takes unreserved office {unreserved}");
+    fn assign(&self, mut results: Vec<(f32, Name)>) -> Outcome {
+        let mut seats = Seats::new(self);
+        let ineligible = self.ineligible();
+        let support = self.support();
+        let mut standings = Vec::new();
+
+        while let Some((score, candidate)) = results.pop() {
+            let Some(regions) = self.candidates.get(&candidate).map(|c| &c.regions) else {
+                tracing::warn!("{candidate} has votes but is not a candidate");
+                continue;
+            };
+            let status = if seats.remaining() == 0 {
+                Status::NotElected
+            } else if ineligible.contains(&candidate) {
+                tracing::warn!("{candidate} is withdrawn or below the minimum support");
+                self.ineligible_status(&candidate)
             } else {
-                tracing::warn!("Could not assign {candidate}");
+                tracing::info!("assigning {candidate} ({} open)", seats.remaining());
+                match seats.take(&candidate, regions) {
+                    Ok(seat) => {
+                        tracing::warn!("{candidate} takes {seat} office ({})", seats.remaining());
+                        Status::Elected(seat)
+                    }
+                    Err(status) => {
+                        tracing::warn!("Could not assign {candidate}");
+                        status
+                    }
+                }
+            };
+            standings.push(Standing {
+                rank: standings.len() + 1,
+                votes: support.get(&candidate).copied().unwrap_or(0),
+                name: candidate,
+                regions: regions.clone(),
+                score,
+                status,
+            });
+        }
+
+        // Earlier winners may have moved to another office to make room for later ones.
+        let seated = seats.seated();
+        for standing in &mut standings {
+            if let Status::Elected(seat) = &mut standing.status {
+                *seat = seated[&standing.name].clone();
             }
         }
 
-        officers.sort();
-        Some(officers)
+        Outcome {
+            standings,
+            ties: Vec::new(),
+            vacant: seats.vacancies(),
+            complete: seats.remaining() == 0,
+        }
     }
 
+    /// Runs a Single Transferable Vote count, keeping every round for display.
+    pub fn stv(&self) -> stv::Count {
+        stv::count(self)
+    }
+
+    /// Candidates ordered from lowest to highest score, along with the ties that were broken.
+    ///
+    /// Single Transferable Vote has no overall ranking; see [`Election::stv`] instead.
+    fn ranking(&self) -> (Vec<(f32, Name)>, Vec<Tie>) {
+        let results = match self.method {
+            CountingMethod::Score | CountingMethod::Stv => self.tally(),
+            CountingMethod::Schulze => schulze::rank(self),
+            CountingMethod::Borda => self.borda(),
+        };
+        self.break_ties(results)
+    }
+
+    /// Counts the election, recording how every candidate placed and why.
+    pub fn outcome(&self) -> Outcome {
+        match self.method {
+            CountingMethod::Stv => self.stv().outcome(self),
+            _ => {
+                let (results, ties) = self.ranking();
+                Outcome {
+                    ties,
+                    ..self.assign(results)
+                }
+            }
+        }
+    }
+
+    #[allow(unused)]
     pub fn run(&self) -> Option<Vec<Name>> {
-        let results = self.tally();
-        self.assign(results)
+        self.outcome().elected()
     }
 }
 
@@ -239,6 +1345,12 @@ mod test {
             vec![("a", 2), ("b", 2), ("c", 0), ("d", 2)],
         ],
         vec![("a", 2.), ("b", 2.), ("c", 2.), ("d", 2.)]; "Allows abstention")]
+    #[test_case(
+        vec![
+            vec![("a", 2), ("b", 0)],
+            vec![("a", 1), ("b", 0)],
+        ],
+        vec![("a", 1.5), ("b", 0.)]; "only abstentions")]
     fn test_tally<N: Into<Name>>(votes: Vec<Vec<(N, usize)>>, expected: Vec<(N, f32)>) {
         let mut election = Election::new(1, 1);
         for (i, ballot) in votes.into_iter().enumerate() {
@@ -253,6 +1365,94 @@ mod test {
         }
     }
 
+    #[test_case(
+        vec![
+            vec![("a", 1), ("b", 0), ("c", 1)],
+            vec![("a", 1), ("b", 1), ("c", 0)],
+            vec![("a", 0), ("b", 0), ("c", 1)],
+        ],
+        vec![("a", 2.), ("b", 1.), ("c", 2.)]; "counts approvals")]
+    fn test_tally_approval<N: Into<Name>>(votes: Vec<Vec<(N, usize)>>, expected: Vec<(N, f32)>) {
+        let mut election = Election::new(1, 1);
+        election.set_kind(ElectionKind::Approval);
+        for (i, ballot) in votes.into_iter().enumerate() {
+            for (n, rank) in ballot {
+                election.vote((i as u64 + 1).into(), n, rank);
+            }
+        }
+        let tally = election.tally();
+        assert_eq!(tally.len(), expected.len());
+        for (n, rank) in expected {
+            assert!(tally.contains(&(rank, n.into())));
+        }
+    }
+
+    #[test_case(
+        Scale { min: 0, max: 10 },
+        vec![
+            vec![("a", 1), ("b", 11), ("c", 0)],
+            vec![("a", 1), ("b", 6), ("c", 3)],
+        ],
+        vec![("a", 0.), ("b", 7.5), ("c", 2.)]; "zero is a score")]
+    #[test_case(
+        Scale { min: 1, max: 3 },
+        vec![
+            vec![("a", 1), ("b", 3)],
+            vec![("a", 3), ("b", 2)],
+        ],
+        vec![("a", 2.), ("b", 2.5)]; "short scale")]
+    fn test_tally_scale<N: Into<Name>>(
+        scale: Scale,
+        votes: Vec<Vec<(N, usize)>>,
+        expected: Vec<(N, f32)>,
+    ) {
+        let mut election = Election::new(1, 1);
+        election.set_scale(scale);
+        for (i, ballot) in votes.into_iter().enumerate() {
+            for (n, rank) in ballot {
+                election.vote((i as u64 + 1).into(), n, rank);
+            }
+        }
+        let tally = election.tally();
+        assert_eq!(tally.len(), expected.len());
+        for (n, rank) in expected {
+            assert!(tally.contains(&(rank, n.into())));
+        }
+    }
+
+    #[test_case("0-10", Some(Scale { min: 0, max: 10 }); "zero based")]
+    #[test_case(" 1 - 3 ", Some(Scale { min: 1, max: 3 }); "whitespace")]
+    #[test_case("5-1", None; "backwards")]
+    #[test_case("0-30", None; "too many options")]
+    #[test_case("10", None; "no range")]
+    fn test_parse_scale(input: &str, expected: Option<Scale>) {
+        assert_eq!(input.parse::<Scale>().ok(), expected);
+    }
+
+    #[test_case(
+        vec![
+            vec![("a", 5), ("b", 3), ("c", 1)],
+            vec![("a", 2), ("b", 2), ("c", 0)],
+            vec![("a", 0), ("b", 4), ("c", 5)],
+        ],
+        vec![("a", 3.5), ("b", 3.5), ("c", 2.)]; "positional points")]
+    fn test_borda<N: Into<Name>>(votes: Vec<Vec<(N, usize)>>, expected: Vec<(N, f32)>) {
+        let mut election = Election::new(1, 1);
+        for n in ["a", "b", "c"] {
+            election.add_candidate(n, "EMEA");
+        }
+        for (i, ballot) in votes.into_iter().enumerate() {
+            for (n, rank) in ballot {
+                election.vote((i as u64 + 1).into(), n, rank);
+            }
+        }
+        let tally = election.borda();
+        assert_eq!(tally.len(), expected.len());
+        for (n, points) in expected {
+            assert!(tally.contains(&(points, n.into())));
+        }
+    }
+
     #[test_case(
         4,
         vec!["AMER"],
@@ -273,7 +1473,7 @@ mod test {
         offices: usize,
         reservations: Vec<R>,
         tally: Vec<(f32, N)>,
-        candidates: Vec<(N, R)>,
+        candidates: Vec<(N, &str)>,
         expected: Vec<N>,
     ) {
         let mut election = Election::new(1, offices);
@@ -289,7 +1489,549 @@ mod test {
             result.push((usize, n.into()));
         }
         let expected = expected.into_iter().map(|n| n.into()).collect::<Vec<_>>();
-        assert_eq!(Some(expected), election.assign(result));
+        assert_eq!(Some(expected), election.assign(result).elected());
+    }
+
+    #[test]
+    fn test_assign_explains_skips() {
+        let mut election = Election::new(1, 3);
+        election.reserve_office("AMER");
+        election.set_min_support(Some(Support::Votes(1)));
+        for (n, r) in [
+            ("a", "AMER"),
+            ("b", "EMEA"),
+            ("c", "EMEA"),
+            ("d", "EMEA"),
+            ("e", "EMEA"),
+        ] {
+            election.add_candidate(n, r);
+        }
+        election.vote(1.into(), "a", 1);
+        election.vote(1.into(), "b", 2);
+        election.vote(1.into(), "c", 3);
+        election.vote(1.into(), "d", 4);
+        election.vote(1.into(), "e", 0);
+
+        let outcome = election.assign(vec![
+            (1., "a".into()),
+            (2., "b".into()),
+            (3., "c".into()),
+            (4., "d".into()),
+            (5., "e".into()),
+        ]);
+        let statuses: Vec<_> = outcome
+            .standings
+            .iter()
+            .map(|s| (s.rank, s.name.to_string(), s.votes, s.status.clone()))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (1, "e".into(), 0, Status::Ineligible),
+                (2, "d".into(), 1, Status::Elected(Seat::Unreserved)),
+                (3, "c".into(), 1, Status::Elected(Seat::Unreserved)),
+                (
+                    4,
+                    "b".into(),
+                    1,
+                    Status::NoOpenOffice {
+                        reserved_for: vec!["AMER".into()]
+                    }
+                ),
+                (
+                    5,
+                    "a".into(),
+                    1,
+                    Status::Elected(Seat::Reserved("AMER".into()))
+                ),
+            ]
+        );
+        assert!(outcome.complete);
+    }
+
+    #[test]
+    fn test_assign_respects_maximum() {
+        let mut election = Election::new(1, 2);
+        election
+            .set_quota(
+                "EMEA",
+                Quota {
+                    min: 0,
+                    max: Some(1),
+                },
+            )
+            .unwrap();
+        for (n, r) in [("a", "EMEA"), ("b", "EMEA"), ("c", "AMER")] {
+            election.add_candidate(n, r);
+        }
+
+        let outcome = election.assign(vec![(1., "c".into()), (2., "b".into()), (3., "a".into())]);
+        assert_eq!(
+            outcome.standings[1].status,
+            Status::RegionFull {
+                region: "EMEA".into(),
+                max: 1
+            }
+        );
+        assert_eq!(outcome.elected(), Some(vec!["a".into(), "c".into()]));
+    }
+
+    #[test_case(VacancyPolicy::Fail, None; "fail")]
+    #[test_case(VacancyPolicy::LeaveVacant, Some(vec!["a"]); "leave vacant")]
+    #[test_case(VacancyPolicy::Release, Some(vec!["a", "b"]); "release")]
+    fn test_assign_vacancies(policy: VacancyPolicy, expected: Option<Vec<&str>>) {
+        let mut election = Election::new(1, 2);
+        election.set_vacancy_policy(policy);
+        election.reserve_office("APAC");
+        for n in ["a", "b", "c"] {
+            election.add_candidate(n, "EMEA");
+        }
+
+        let outcome = election.assign(vec![(1., "c".into()), (2., "b".into()), (3., "a".into())]);
+        assert_eq!(
+            outcome.elected(),
+            expected.map(|e| e.into_iter().map(Name::from).collect())
+        );
+        assert_eq!(outcome.vacant.is_empty(), policy == VacancyPolicy::Release);
+    }
+
+    #[test_case(vec![("EMEA", 2, None), ("AMER", 2, None)]; "more reserved than offices")]
+    #[test_case(vec![("EMEA", 2, Some(1))]; "minimum above maximum")]
+    fn test_invalid_quotas(quotas: Vec<(&str, usize, Option<usize>)>) {
+        let mut election = Election::new(1, 3);
+        let results: Vec<_> = quotas
+            .into_iter()
+            .map(|(region, min, max)| election.set_quota(region, Quota { min, max }))
+            .collect();
+        assert!(results.last().unwrap().is_err());
+        assert!(election.quotas.values().map(|q| q.min).sum::<usize>() <= 3);
+    }
+
+    #[test]
+    fn test_migrate_reserved_offices() {
+        let mut election: Election = serde_json::from_value(serde_json::json!({
+            "owner": 1,
+            "candidates": { "a": "AMER", "b": ["AMER", "EMEA"], "c": [] },
+            "offices": 3,
+            "reserved_offices": ["AMER", "EMEA", "AMER"],
+            "ballots": {},
+        }))
+        .unwrap();
+        election.migrate();
+        assert_eq!(
+            election.quotas,
+            BTreeMap::from([
+                ("AMER".into(), Quota { min: 2, max: None }),
+                ("EMEA".into(), Quota { min: 1, max: None }),
+            ])
+        );
+        assert_eq!(
+            election
+                .candidates
+                .iter()
+                .map(|(n, c)| (n.clone(), c.regions.clone()))
+                .collect::<BTreeMap<_, _>>(),
+            BTreeMap::from([
+                ("a".into(), "AMER".into()),
+                ("b".into(), "AMER/EMEA".into()),
+                ("c".into(), "".into()),
+            ])
+        );
+    }
+
+    #[test_case(State::Open, false; "open")]
+    #[test_case(State::Closed, true; "closed")]
+    #[test_case(State::Certified, true; "certified")]
+    fn test_migrate_seals_closed_elections(state: State, sealed: bool) {
+        let mut election = Election::new(1, 1);
+        election.state = state;
+        election.migrate();
+        assert_eq!(election.tie_seed.is_some(), sealed);
+    }
+
+    #[test_case("3", Some(Support::Votes(3)); "votes")]
+    #[test_case(" 12.5% ", Some(Support::Percent(12.5)); "percent")]
+    #[test_case("120%", None; "over a hundred percent")]
+    #[test_case("lots", None; "not a number")]
+    fn test_parse_support(input: &str, expected: Option<Support>) {
+        assert_eq!(input.parse::<Support>().ok(), expected);
+    }
+
+    #[test_case(Support::Votes(2), vec!["a"]; "absolute")]
+    #[test_case(Support::Percent(50.), vec!["a"]; "half of ballots")]
+    #[test_case(Support::Percent(60.), vec!["a", "c"]; "rounds up")]
+    fn test_minimum_support(support: Support, expected: Vec<&str>) {
+        let mut election = Election::new(1, 1);
+        for n in ["a", "b", "c"] {
+            election.add_candidate(n, "EMEA");
+        }
+        election.set_min_support(Some(support));
+
+        election.vote(1.into(), "a", 5);
+        election.vote(1.into(), "b", 1);
+        election.vote(1.into(), "c", 3);
+        election.vote(2.into(), "b", 2);
+        election.vote(2.into(), "c", 3);
+        election.vote(3.into(), "b", 3);
+        election.vote(3.into(), "a", 0);
+        election.vote(4.into(), "b", 3);
+
+        let expected: BTreeSet<Name> = expected.into_iter().map(Name::from).collect();
+        assert_eq!(election.ineligible(), expected.clone());
+
+        let elected = election.run().unwrap();
+        assert!(elected.iter().all(|n| !expected.contains(n)));
+    }
+
+    #[test]
+    fn test_only_abstentions_rank_last() {
+        let mut election = Election::new(1, 1);
+        election.add_candidate("a", "EMEA");
+        election.add_candidate("b", "EMEA");
+        election.vote(1.into(), "a", 1);
+        election.vote(1.into(), "b", 0);
+        election.vote(2.into(), "b", 0);
+        assert_eq!(election.run(), Some(vec!["a".into()]));
+    }
+
+    #[test]
+    fn test_bayesian_average() {
+        let mut election = Election::new(1, 1);
+        election.set_bayesian(true);
+
+        election.vote(1.into(), "a", 5);
+        election.vote(1.into(), "b", 4);
+        election.vote(2.into(), "b", 4);
+        election.vote(3.into(), "b", 4);
+
+        // Two votes per candidate at the mean of 4.25 are mixed into each average.
+        let tally = election.tally();
+        assert!(tally.contains(&(4.5, "a".into())));
+        assert!(tally.contains(&(4.1, "b".into())));
+    }
+
+    fn time(s: &str) -> DateTime<Utc> {
+        parse_time(s).unwrap()
+    }
+
+    #[test_case("2024-06-01 18:00", Some("2024-06-01T18:00:00Z"); "minutes")]
+    #[test_case("2024-06-01T20:00:00+02:00", Some("2024-06-01T18:00:00Z"); "rfc 3339")]
+    #[test_case("tomorrow", None; "not a time")]
+    fn test_parse_time(input: &str, expected: Option<&str>) {
+        assert_eq!(
+            parse_time(input).ok(),
+            expected.map(|e| DateTime::parse_from_rfc3339(e).unwrap().to_utc())
+        );
+    }
+
+    #[test_case("2024-06-01 12:00", State::Draft, false; "before opening")]
+    #[test_case("2024-06-01 18:00", State::Open, true; "opening")]
+    #[test_case("2024-06-08 17:59", State::Open, true; "before closing")]
+    #[test_case("2024-06-08 18:00", State::Closed, true; "closing")]
+    fn test_advance(now: &str, expected: State, changed: bool) {
+        let mut election = Election::new(1, 1);
+        election
+            .set_schedule(
+                Some(time("2024-06-01 18:00")),
+                Some(time("2024-06-08 18:00")),
+                time("2024-05-01 00:00"),
+            )
+            .unwrap();
+        assert_eq!(election.state(), State::Draft);
+        assert_eq!(election.advance(time(now)), changed);
+        assert_eq!(election.state(), expected);
+    }
+
+    #[test]
+    fn test_scheduled_close_waits_for_announcement() {
+        let mut election = Election::new(1, 1);
+        election.set_announce_in(Some(5.into()));
+        election
+            .set_schedule(
+                None,
+                Some(time("2024-06-08 18:00")),
+                time("2024-06-01 00:00"),
+            )
+            .unwrap();
+        assert!(election.advance(time("2024-06-08 18:00")));
+        assert!(election.announcement_pending());
+        // Still pending on later ticks until it is posted.
+        assert!(!election.advance(time("2024-06-08 18:01")));
+        assert!(election.announcement_pending());
+        election.set_announced();
+        assert!(!election.announcement_pending());
+    }
+
+    #[test]
+    fn test_schedule_must_close_after_opening() {
+        let mut election = Election::new(1, 1);
+        let now = time("2024-05-01 00:00");
+        assert!(election
+            .set_schedule(
+                Some(time("2024-06-08 18:00")),
+                Some(time("2024-06-01 18:00")),
+                now
+            )
+            .is_err());
+        assert!(election
+            .set_schedule(None, Some(time("2024-04-01 18:00")), now)
+            .is_err());
+        assert_eq!(election.state(), State::Open);
+    }
+
+    #[test]
+    fn test_certify_requires_closing() {
+        let mut election = Election::new(1, 1);
+        election.add_candidate("a", "EMEA");
+        election.vote(1.into(), "a", 1);
+        assert!(election.certify().is_err());
+        assert!(election.close());
+        assert!(!election.close());
+        assert!(election.certify().is_ok());
+        assert_eq!(election.state(), State::Certified);
+        assert!(election.tie_seed.is_some());
+    }
+
+    #[test]
+    fn test_results_are_announced_once_certified() {
+        let mut election = Election::new(1, 1);
+        election.add_candidate("a", "EMEA");
+        election.vote(1.into(), "a", 5);
+        assert!(election.check_announce().is_err());
+        election.close();
+        assert!(election.check_announce().is_err());
+        election.certify().unwrap();
+        assert!(election.check_announce().is_ok());
+        election.set_announced();
+        assert!(election.check_announce().is_err());
+    }
+
+    #[test]
+    fn test_incomplete_results_cant_be_certified() {
+        let mut election = Election::new(1, 2);
+        election.add_candidate("a", "EMEA");
+        election.vote(1.into(), "a", 1);
+        assert!(election.close());
+        assert!(election.certify().is_err());
+        assert_eq!(election.state(), State::Closed);
+        assert!(election.winners().is_empty());
+    }
+
+    #[test]
+    fn test_remove_candidate() {
+        let mut election = Election::new(1, 1);
+        election.add_candidate("a", "EMEA");
+        election.add_candidate("b", "EMEA");
+        election.vote(1.into(), "a", 5);
+        election.vote(1.into(), "b", 3);
+        election.set_lots(vec!["b".into(), "a".into()]);
+
+        assert!(election.remove_candidate(&"a".into(), Utc::now()));
+        assert!(!election.remove_candidate(&"a".into(), Utc::now()));
+        assert_eq!(election.nominated, vec![Name::from("b")]);
+        assert_eq!(election.lots, vec![Name::from("b")]);
+        assert_eq!(
+            election.ballots[&1.into()].votes,
+            BTreeMap::from([("b".into(), 3)])
+        );
+    }
+
+    #[test]
+    fn test_remove_candidate_during_a_vote() {
+        let mut election = Election::new(1, 1);
+        election.add_candidate("a", "EMEA");
+        election.add_candidate("b", "EMEA");
+        // The voter filled in their ballot before "a" was removed.
+        let ballot = Ballot {
+            votes: BTreeMap::from([("a".into(), 5), ("b".into(), 3)]),
+        };
+        assert!(election.remove_candidate(&"a".into(), Utc::now()));
+        election.cast(1.into(), ballot, None, Utc::now()).unwrap();
+
+        assert_eq!(
+            election.ballots[&1.into()].votes,
+            BTreeMap::from([("b".into(), 3)])
+        );
+        assert_eq!(election.run(), Some(vec!["b".into()]));
+    }
+
+    #[test]
+    fn test_votes_for_unknown_candidates_are_skipped() {
+        let mut election = Election::new(1, 1);
+        election.add_candidate("b", "EMEA");
+        election.vote(1.into(), "a", 5);
+        election.vote(1.into(), "b", 3);
+        assert_eq!(election.run(), Some(vec!["b".into()]));
+    }
+
+    #[test_case(Some("Hi".into()), Some("https://example.com".into()), true; "valid")]
+    #[test_case(None, None, true; "empty")]
+    #[test_case(None, Some("example.com".into()), false; "link without scheme")]
+    #[test_case(Some("x".repeat(2001)), None, false; "statement too long")]
+    fn test_set_profile(statement: Option<String>, link: Option<String>, valid: bool) {
+        let mut election = Election::new(1, 1);
+        election.add_candidate("a", "EMEA");
+        assert_eq!(
+            election
+                .set_profile(&"a".into(), statement, link, Some(2.into()))
+                .is_ok(),
+            valid
+        );
+        assert_eq!(election.candidates[&"a".into()].user().is_some(), valid);
+        assert!(election.set_profile(&"b".into(), None, None, None).is_err());
+    }
+
+    #[test]
+    fn test_profiles_are_saved() {
+        let mut election = Election::new(1, 1);
+        election.add_candidate("a", "AMER/EMEA");
+        election
+            .set_profile(
+                &"a".into(),
+                Some("Hi".into()),
+                Some("https://example.com".into()),
+                Some(2.into()),
+            )
+            .unwrap();
+        let saved: Election =
+            serde_json::from_value(serde_json::to_value(&election).unwrap()).unwrap();
+        assert_eq!(saved.candidates, election.candidates);
+    }
+
+    #[test]
+    fn test_accounts_belong_to_one_candidate() {
+        let mut election = Election::new(1, 1);
+        election.add_candidate("a", "EMEA");
+        election.add_candidate("b", "EMEA");
+        assert!(election
+            .set_profile(&"a".into(), None, None, Some(2.into()))
+            .is_ok());
+        assert!(election
+            .set_profile(&"b".into(), None, None, Some(2.into()))
+            .is_err());
+        assert_eq!(election.mention(&"a".into()), "**a** (<@2>)");
+        assert_eq!(election.mention(&"b".into()), "**b**");
+    }
+
+    #[test_case(false, vec!["a", "b"]; "allowed")]
+    #[test_case(true, vec!["b"]; "forbidden")]
+    fn test_self_votes(forbid: bool, expected: Vec<&str>) {
+        let mut election = Election::new(1, 1);
+        election.set_forbid_self_votes(forbid);
+        election.add_candidate("a", "EMEA");
+        election.add_candidate("b", "EMEA");
+        election
+            .set_profile(&"a".into(), None, None, Some(2.into()))
+            .unwrap();
+        assert_eq!(
+            election
+                .ballot_candidates(2.into())
+                .map(|(n, _)| n.clone())
+                .collect::<Vec<_>>(),
+            expected.into_iter().map(Name::from).collect::<Vec<_>>()
+        );
+        assert_eq!(election.ballot_candidates(3.into()).count(), 2);
+    }
+
+    #[test]
+    fn test_winners() {
+        let mut election = Election::new(1, 2);
+        for n in ["a", "b", "c"] {
+            election.add_candidate(n, "EMEA");
+        }
+        election
+            .set_profile(&"a".into(), None, None, Some(2.into()))
+            .unwrap();
+        election
+            .set_profile(&"c".into(), None, None, Some(3.into()))
+            .unwrap();
+        election.vote(1.into(), "a", 5);
+        election.vote(1.into(), "b", 4);
+        election.vote(1.into(), "c", 1);
+        assert_eq!(election.winners(), vec![serenity::UserId::from(2)]);
+    }
+
+    #[test]
+    fn test_outgoing_holders() {
+        let mut election = Election::new(1, 1);
+        election.add_candidate("a", "EMEA");
+        election
+            .set_profile(&"a".into(), None, None, Some(2.into()))
+            .unwrap();
+        let holders = || [2.into(), 3.into()];
+        assert!(election.outgoing_holders(holders()).is_empty());
+
+        election.set_replace_role_holders(true);
+        // Without a complete result there are no winners, so nobody loses the role.
+        assert!(election.outgoing_holders(holders()).is_empty());
+        election.vote(1.into(), "a", 5);
+        assert_eq!(
+            election.outgoing_holders(holders()),
+            vec![serenity::UserId::from(3)]
+        );
+    }
+
+    #[test_case("a"; "same name")]
+    #[test_case("A"; "different case")]
+    fn test_duplicate_candidates_are_rejected(name: &str) {
+        let mut election = Election::new(1, 1);
+        assert!(election.add_candidate("a", "EMEA"));
+        assert!(!election.add_candidate(name, "AMER"));
+        assert_eq!(election.candidates.len(), 1);
+        assert_eq!(
+            election.candidates[&"a".into()].regions,
+            Regions::from("EMEA")
+        );
+    }
+
+    #[test]
+    fn test_withdrawn_candidates_are_not_elected() {
+        let mut election = Election::new(1, 1);
+        for n in ["a", "b"] {
+            election.add_candidate(n, "EMEA");
+        }
+        election.vote(1.into(), "a", 5);
+        election.vote(1.into(), "b", 3);
+        assert!(election.withdraw_candidate(&"a".into()));
+        assert!(!election.withdraw_candidate(&"a".into()));
+
+        assert_eq!(
+            election
+                .ballot_candidates(1.into())
+                .map(|(n, _)| n)
+                .collect::<Vec<_>>(),
+            vec![&Name::from("b")]
+        );
+        let outcome = election.outcome();
+        assert_eq!(outcome.elected(), Some(vec!["b".into()]));
+        assert_eq!(outcome.standings[0].status, Status::Withdrawn);
+    }
+
+    #[test_case(0, false; "no offices")]
+    #[test_case(1, false; "fewer than reserved")]
+    #[test_case(2, true; "as many as reserved")]
+    fn test_set_offices(offices: usize, ok: bool) {
+        let mut election = Election::new(1, 3);
+        election.reserve_office("AMER");
+        election.reserve_office("EMEA");
+        assert_eq!(election.set_offices(offices).is_ok(), ok);
+    }
+
+    #[test]
+    fn test_reopen() {
+        let mut election = Election::new(1, 1);
+        let now = time("2024-05-01 00:00");
+        assert!(election.reopen(None, now).is_err());
+        election.close();
+        assert!(election
+            .reopen(Some(time("2024-04-01 00:00")), now)
+            .is_err());
+        election
+            .reopen(Some(time("2024-06-01 00:00")), now)
+            .unwrap();
+        assert_eq!(election.state(), State::Open);
+        assert!(election.advance(time("2024-06-01 00:00")));
+        assert_eq!(election.state(), State::Closed);
     }
 
     #[test]
@@ -315,7 +2057,14 @@ mod test {
 
         election.vote(4.into(), "e", 5);
         election.vote(4.into(), "b", 1);
+        election.close();
 
-        println!("{:?}", election.run());
+        // a and e tie, but both are elected whichever way the draw goes. d takes EMEA's office.
+        let mut winners = election.run().unwrap();
+        winners.sort();
+        assert_eq!(
+            winners,
+            vec![Name::from("a"), "c".into(), "d".into(), "e".into()]
+        );
     }
 }
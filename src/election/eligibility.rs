@@ -0,0 +1,193 @@
+//! Who may vote in an election.
+
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use poise::serenity_prelude as serenity;
+use serde::{Deserialize, Serialize};
+
+/// Rules a member must meet before they can vote. The default lets everyone vote.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Eligibility {
+    /// Voters must hold at least one of these roles, if there are any.
+    #[serde(default)]
+    required_roles: BTreeSet<serenity::RoleId>,
+    /// Members holding any of these roles can't vote.
+    #[serde(default)]
+    excluded_roles: BTreeSet<serenity::RoleId>,
+    #[serde(default)]
+    min_membership_days: Option<u32>,
+    #[serde(default)]
+    min_account_days: Option<u32>,
+}
+
+/// What the eligibility rules need to know about a member.
+#[derive(Debug, Clone)]
+pub struct Voter {
+    pub roles: Vec<serenity::RoleId>,
+    /// When they joined the server, if known.
+    pub joined_at: Option<DateTime<Utc>>,
+    /// When their Discord account was created.
+    pub created_at: DateTime<Utc>,
+}
+
+impl Eligibility {
+    /// Lets members with the role vote, and stops excluding it.
+    pub fn require_role(&mut self, role: serenity::RoleId) {
+        self.excluded_roles.remove(&role);
+        self.required_roles.insert(role);
+    }
+
+    /// Stops members with the role voting, and stops requiring it.
+    pub fn exclude_role(&mut self, role: serenity::RoleId) {
+        self.required_roles.remove(&role);
+        self.excluded_roles.insert(role);
+    }
+
+    /// Neither requires nor excludes the role.
+    pub fn clear_role(&mut self, role: serenity::RoleId) {
+        self.required_roles.remove(&role);
+        self.excluded_roles.remove(&role);
+    }
+
+    pub fn set_min_membership_days(&mut self, days: Option<u32>) {
+        self.min_membership_days = days.filter(|d| *d > 0);
+    }
+
+    pub fn set_min_account_days(&mut self, days: Option<u32>) {
+        self.min_account_days = days.filter(|d| *d > 0);
+    }
+
+    /// Every rule `voter` doesn't meet, explained to them. Empty if they may vote.
+    pub fn problems(&self, voter: &Voter, now: DateTime<Utc>) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !self.required_roles.is_empty()
+            && !voter.roles.iter().any(|r| self.required_roles.contains(r))
+        {
+            problems.push(format!(
+                "You need one of these roles: {}",
+                mentions(&self.required_roles)
+            ));
+        }
+        let excluded: BTreeSet<_> = voter
+            .roles
+            .iter()
+            .filter(|r| self.excluded_roles.contains(r))
+            .copied()
+            .collect();
+        if !excluded.is_empty() {
+            problems.push(format!("Members with {} can't vote", mentions(&excluded)));
+        }
+        if let Some(days) = self.min_membership_days {
+            match voter.joined_at {
+                Some(joined) if joined + TimeDelta::days(days.into()) <= now => {}
+                Some(joined) => problems.push(format!(
+                    "Voters must have been members for {days} days. You can vote from <t:{}:f>",
+                    (joined + TimeDelta::days(days.into())).timestamp()
+                )),
+                None => problems.push(format!(
+                    "Voters must have been members for {days} days, and your join date is unknown"
+                )),
+            }
+        }
+        if let Some(days) = self.min_account_days {
+            let from = voter.created_at + TimeDelta::days(days.into());
+            if from > now {
+                problems.push(format!(
+                    "Voters' accounts must be {days} days old. You can vote from <t:{}:f>",
+                    from.timestamp()
+                ));
+            }
+        }
+        problems
+    }
+
+    /// The rules in brief for the election embed, or `None` if everyone may vote.
+    pub fn describe(&self) -> Option<String> {
+        let mut rules = Vec::new();
+        if !self.required_roles.is_empty() {
+            rules.push(format!("* Has {}", mentions(&self.required_roles)));
+        }
+        if !self.excluded_roles.is_empty() {
+            rules.push(format!("* Doesn't have {}", mentions(&self.excluded_roles)));
+        }
+        if let Some(days) = self.min_membership_days {
+            rules.push(format!("* Member for at least {days} days"));
+        }
+        if let Some(days) = self.min_account_days {
+            rules.push(format!("* Account at least {days} days old"));
+        }
+        (!rules.is_empty()).then(|| rules.join("\n"))
+    }
+}
+
+/// Role mentions separated by "or".
+fn mentions(roles: &BTreeSet<serenity::RoleId>) -> String {
+    roles
+        .iter()
+        .map(|r| format!("<@&{r}>"))
+        .collect::<Vec<_>>()
+        .join(" or ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_case::test_case;
+
+    fn time(days: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(0, 0).unwrap() + TimeDelta::days(days)
+    }
+
+    fn voter(roles: &[u64], joined: Option<i64>, created: i64) -> Voter {
+        Voter {
+            roles: roles.iter().map(|r| serenity::RoleId::new(*r)).collect(),
+            joined_at: joined.map(time),
+            created_at: time(created),
+        }
+    }
+
+    fn rules() -> Eligibility {
+        let mut rules = Eligibility::default();
+        rules.require_role(1.into());
+        rules.require_role(2.into());
+        rules.exclude_role(3.into());
+        rules.set_min_membership_days(Some(30));
+        rules.set_min_account_days(Some(90));
+        rules
+    }
+
+    #[test]
+    fn test_everyone_may_vote_by_default() {
+        let rules = Eligibility::default();
+        assert!(rules.problems(&voter(&[], None, 100), time(100)).is_empty());
+        assert_eq!(rules.describe(), None);
+    }
+
+    #[test_case(&[2], Some(0), 0, &[]; "eligible")]
+    #[test_case(&[4], Some(0), 0, &["one of these roles"]; "missing role")]
+    #[test_case(&[1, 3], Some(0), 0, &["<@&3> can't vote"]; "excluded role")]
+    #[test_case(&[1], Some(80), 0, &["vote from <t:9504000:f>"]; "new member")]
+    #[test_case(&[1], None, 0, &["join date is unknown"]; "unknown join date")]
+    #[test_case(&[1], Some(0), 20, &["accounts must be 90 days old"]; "new account")]
+    #[test_case(&[3], Some(99), 99, &["one of", "can't vote", "members for", "accounts"]; "everything")]
+    fn test_problems(roles: &[u64], joined: Option<i64>, created: i64, expected: &[&str]) {
+        let problems = rules().problems(&voter(roles, joined, created), time(100));
+        assert_eq!(problems.len(), expected.len(), "{problems:?}");
+        for (problem, expected) in problems.iter().zip(expected) {
+            assert!(
+                problem.contains(expected),
+                "{problem} should mention {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_roles_are_required_or_excluded() {
+        let mut rules = rules();
+        rules.exclude_role(1.into());
+        rules.clear_role(2.into());
+        assert_eq!(rules.required_roles, BTreeSet::new());
+        assert_eq!(rules.excluded_roles, BTreeSet::from([1.into(), 3.into()]));
+    }
+}
@@ -0,0 +1,246 @@
+//! The ballot log.
+//!
+//! Every change to the ballots is appended to a log, each entry holding the SHA-256 hash of the
+//! one before. Replaying the log must give the ballots on record, so editing a ballot in the
+//! data file by hand shows up unless the whole log after it is rewritten too, and that changes
+//! the head published with the results.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+
+use super::{Ballot, Election, Name};
+
+/// What happened to the ballots. Voters are user IDs, or ballot tokens in secret elections.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(super) enum Event {
+    /// A ballot cast before the log was kept.
+    Imported {
+        voter: String,
+        ballot: Ballot,
+    },
+    Cast {
+        voter: String,
+        ballot: Ballot,
+    },
+    Replaced {
+        voter: String,
+        ballot: Ballot,
+    },
+    Voided {
+        voter: String,
+    },
+    /// Votes for the candidate were struck from every ballot.
+    CandidateRemoved {
+        name: Name,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct Entry {
+    at: DateTime<Utc>,
+    #[serde(flatten)]
+    event: Event,
+    hash: String,
+}
+
+/// The hash the first entry chains from.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+fn hash(prev: &str, at: &DateTime<Utc>, event: &Event) -> String {
+    #[derive(Serialize)]
+    struct Hashed<'a> {
+        prev: &'a str,
+        at: &'a DateTime<Utc>,
+        #[serde(flatten)]
+        event: &'a Event,
+    }
+    let json = serde_json::to_vec(&Hashed { prev, at, event }).unwrap_or_default();
+    digest::digest(&digest::SHA256, &json)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+impl Election {
+    pub(super) fn record(&mut self, event: Event, now: DateTime<Utc>) {
+        let prev = self.log.last().map_or(GENESIS, |e| &e.hash);
+        let hash = hash(prev, &now, &event);
+        self.log.push(Entry {
+            at: now,
+            event,
+            hash,
+        });
+    }
+
+    /// Starts the log of an election from before it was kept with the ballots already cast.
+    pub(super) fn import_ballots(&mut self, now: DateTime<Utc>) {
+        if !self.log.is_empty() {
+            return;
+        }
+        let ballots: Vec<_> = match &self.secret {
+            Some(secret) => secret.ballots().collect(),
+            None => self
+                .ballots
+                .iter()
+                .map(|(user, ballot)| (user.to_string(), ballot.clone()))
+                .collect(),
+        };
+        for (voter, ballot) in ballots {
+            self.record(Event::Imported { voter, ballot }, now);
+        }
+    }
+
+    /// The hash of the last entry in the ballot log, which commits to every ballot cast.
+    pub fn log_head(&self) -> &str {
+        self.log.last().map_or(GENESIS, |e| &e.hash)
+    }
+
+    pub fn log_len(&self) -> usize {
+        self.log.len()
+    }
+
+    /// Candidates whose votes were struck from the ballots, in the order they were removed.
+    pub fn removed_candidates(&self) -> Vec<&Name> {
+        self.log
+            .iter()
+            .filter_map(|e| match &e.event {
+                Event::CandidateRemoved { name } => Some(name),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Checks that the log is unbroken and that replaying it gives the ballots on record.
+    pub fn verify_log(&self) -> Result<(), String> {
+        let mut prev = GENESIS;
+        let mut ballots: BTreeMap<String, Ballot> = BTreeMap::new();
+        for (i, entry) in self.log.iter().enumerate() {
+            if hash(prev, &entry.at, &entry.event) != entry.hash {
+                return Err(format!(
+                    "Entry {} of the ballot log has been altered",
+                    i + 1
+                ));
+            }
+            prev = &entry.hash;
+            match &entry.event {
+                Event::Imported { voter, ballot }
+                | Event::Cast { voter, ballot }
+                | Event::Replaced { voter, ballot } => {
+                    ballots.insert(voter.clone(), ballot.clone());
+                }
+                Event::Voided { voter } => {
+                    ballots.remove(voter);
+                }
+                Event::CandidateRemoved { name } => {
+                    for ballot in ballots.values_mut() {
+                        ballot.votes.remove(name);
+                    }
+                }
+            }
+        }
+
+        let matches = match &self.secret {
+            Some(secret) => secret.voted_count() == ballots.len() && secret.ballots().eq(ballots),
+            None => self
+                .ballots
+                .iter()
+                .map(|(user, ballot)| (user.to_string(), ballot.clone()))
+                .eq(ballots),
+        };
+        if !matches {
+            return Err("The ballots on record don't match the ballot log".into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::election::BallotKey;
+    use chrono::TimeDelta;
+
+    fn time(minutes: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(0, 0).unwrap() + TimeDelta::minutes(minutes)
+    }
+
+    fn ballot(rank: usize) -> Ballot {
+        Ballot {
+            votes: BTreeMap::from([("a".into(), rank), ("b".into(), 1)]),
+        }
+    }
+
+    fn election(secret: bool) -> Election {
+        let key = BallotKey::new(b"key");
+        let mut election = Election::new(1, 1);
+        election.add_candidate("a", "EMEA");
+        election.add_candidate("b", "EMEA");
+        if secret {
+            election.make_secret();
+        }
+        election
+            .cast(2.into(), ballot(1), Some(&key), time(1))
+            .unwrap();
+        election
+            .cast(3.into(), ballot(2), Some(&key), time(2))
+            .unwrap();
+        election
+            .cast(2.into(), ballot(3), Some(&key), time(3))
+            .unwrap();
+        election.void(3.into(), Some(&key), time(4)).unwrap();
+        election.remove_candidate(&"b".into(), time(5));
+        election
+    }
+
+    #[test]
+    fn test_log_replays_to_the_ballots() {
+        for secret in [false, true] {
+            let election = election(secret);
+            assert_eq!(election.log_len(), 5);
+            assert_eq!(election.verify_log(), Ok(()));
+            assert_eq!(election.removed_candidates(), vec![&Name::from("b")]);
+
+            // The log survives being saved and loaded.
+            let saved = serde_json::to_string(&election).unwrap();
+            let loaded: Election = serde_json::from_str(&saved).unwrap();
+            assert_eq!(loaded.verify_log(), Ok(()));
+            assert_eq!(loaded.log_head(), election.log_head());
+        }
+    }
+
+    #[test]
+    fn test_edited_ballots_are_detected() {
+        let mut election = election(false);
+        election
+            .ballots
+            .get_mut(&2.into())
+            .unwrap()
+            .votes
+            .insert("a".into(), 5);
+        assert!(election.verify_log().unwrap_err().contains("don't match"));
+    }
+
+    #[test]
+    fn test_edited_log_is_detected() {
+        let mut saved = serde_json::to_value(election(false)).unwrap();
+        saved["log"][0]["ballot"]["votes"]["a"] = 5.into();
+        let election: Election = serde_json::from_value(saved).unwrap();
+        assert!(election.verify_log().unwrap_err().contains("Entry 1"));
+    }
+
+    #[test]
+    fn test_ballots_from_before_the_log_are_imported() {
+        let mut election = Election::new(1, 1);
+        election.add_candidate("a", "EMEA");
+        election.vote(2.into(), "a", 1);
+        assert!(election.verify_log().is_err());
+        election.import_ballots(time(0));
+        assert_eq!(election.log_len(), 1);
+        assert_eq!(election.verify_log(), Ok(()));
+    }
+}
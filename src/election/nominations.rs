@@ -0,0 +1,289 @@
+//! Candidate lists supplied when an election is created.
+//!
+//! Lists can be typed one candidate per line (`Name; AMER/EMEA`), uploaded as CSV with `name` and
+//! `regions` columns, or uploaded as a JSON array of `{"name": ..., "regions": ...}` objects.
+//! Every problem is reported at once, with the line or entry it was found on.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use super::{Name, Regions};
+
+/// A candidate to add to an election.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nomination {
+    pub name: Name,
+    pub regions: Regions,
+}
+
+/// Parses one candidate per line, with regions after a `;`. Blank lines are skipped.
+pub fn parse_lines(text: &str) -> Result<Vec<Nomination>, anyhow::Error> {
+    validate(
+        "Line",
+        text.lines().enumerate().filter_map(|(i, line)| {
+            if line.trim().is_empty() {
+                return None;
+            }
+            let (name, regions) = line.split_once(';').unwrap_or((line, ""));
+            Some((i + 1, Ok((name.to_string(), Regions::from(regions)))))
+        }),
+    )
+}
+
+/// Parses the comma separated `name;regions` list accepted by the `candidates` option.
+pub fn parse_list(text: &str) -> Result<Vec<Nomination>, anyhow::Error> {
+    validate(
+        "Entry",
+        text.split(',').enumerate().map(|(i, entry)| {
+            let (name, regions) = entry.split_once(';').unwrap_or((entry, ""));
+            (i + 1, Ok((name.to_string(), Regions::from(regions))))
+        }),
+    )
+}
+
+/// Parses CSV with a name column and an optional regions column. A header row naming the
+/// columns is skipped.
+pub fn parse_csv(text: &str) -> Result<Vec<Nomination>, anyhow::Error> {
+    validate(
+        "Line",
+        text.lines().enumerate().filter_map(|(i, line)| {
+            if line.trim().is_empty() {
+                return None;
+            }
+            let fields = match csv_fields(line) {
+                Ok(fields) => fields,
+                Err(e) => return Some((i + 1, Err(e))),
+            };
+            if i == 0 && fields[0].trim().eq_ignore_ascii_case("name") {
+                return None;
+            }
+            Some((
+                i + 1,
+                match fields.as_slice() {
+                    [name] => Ok((name.clone(), Regions::default())),
+                    [name, regions] => Ok((name.clone(), Regions::from(regions.as_str()))),
+                    _ => Err(format!("expected 2 columns, found {}", fields.len())),
+                },
+            ))
+        }),
+    )
+}
+
+/// Splits a CSV line into fields. Quoted fields may contain commas and doubled quotes.
+fn csv_fields(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().expect("there is always a field");
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            ',' if !quoted => fields.push(String::new()),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quote".into());
+    }
+    Ok(fields)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RegionsInput {
+    Text(String),
+    List(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    name: String,
+    #[serde(default)]
+    regions: Option<RegionsInput>,
+}
+
+/// Parses a JSON array of candidates. Regions may be a list or a `/` separated string.
+pub fn parse_json(text: &str) -> Result<Vec<Nomination>, anyhow::Error> {
+    let entries: Vec<serde_json::Value> = serde_json::from_str(text)
+        .map_err(|e| anyhow::anyhow!("Candidates must be a JSON array: {e}"))?;
+    validate(
+        "Entry",
+        entries.into_iter().enumerate().map(|(i, entry)| {
+            let entry = serde_json::from_value::<Entry>(entry).map_err(|e| e.to_string());
+            (
+                i + 1,
+                entry.map(|entry| {
+                    let regions = match entry.regions {
+                        None => Regions::default(),
+                        Some(RegionsInput::Text(regions)) => Regions::from(regions),
+                        Some(RegionsInput::List(regions)) => Regions::from(regions.join("/")),
+                    };
+                    (entry.name, regions)
+                }),
+            )
+        }),
+    )
+}
+
+/// Parses an uploaded candidate list, choosing the format from the file name.
+pub fn parse_file(filename: &str, contents: &[u8]) -> Result<Vec<Nomination>, anyhow::Error> {
+    let text = std::str::from_utf8(contents)
+        .map_err(|_| anyhow::anyhow!("{filename} is not a text file"))?;
+    let extension = filename.rsplit_once('.').map(|(_, e)| e.to_lowercase());
+    match extension.as_deref() {
+        Some("json") => parse_json(text),
+        Some("csv") | Some("txt") => parse_csv(text),
+        _ => anyhow::bail!("Candidate lists must be .csv or .json files"),
+    }
+}
+
+/// Checks every entry, reporting each bad one by its `unit` and number.
+fn validate(
+    unit: &str,
+    entries: impl IntoIterator<Item = (usize, Result<(String, Regions), String>)>,
+) -> Result<Vec<Nomination>, anyhow::Error> {
+    let mut nominations = Vec::new();
+    let mut errors = Vec::new();
+    // Names are compared ignoring case and surrounding spaces.
+    let mut seen = BTreeMap::<String, usize>::new();
+    for (number, entry) in entries {
+        let (name, regions) = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                errors.push(format!("{unit} {number}: {e}"));
+                continue;
+            }
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            errors.push(format!("{unit} {number}: missing a name"));
+            continue;
+        }
+        if let Some(first) = seen.insert(name.to_lowercase(), number) {
+            errors.push(format!(
+                "{unit} {number}: {name} is a duplicate of {} {first}",
+                unit.to_lowercase()
+            ));
+            continue;
+        }
+        nominations.push(Nomination {
+            name: name.into(),
+            regions,
+        });
+    }
+    if !errors.is_empty() {
+        anyhow::bail!("The candidate list has problems:\n{}", errors.join("\n"));
+    }
+    if nominations.is_empty() {
+        anyhow::bail!("The candidate list is empty");
+    }
+    Ok(nominations)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_case::test_case;
+
+    fn nominations(expected: &[(&str, &str)]) -> Vec<Nomination> {
+        expected
+            .iter()
+            .map(|(name, regions)| Nomination {
+                name: (*name).into(),
+                regions: (*regions).into(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_lines() {
+        assert_eq!(
+            parse_lines("Smith, Jo; AMER/EMEA\n\n  Ada ;EMEA\nCy").unwrap(),
+            nominations(&[("Smith, Jo", "AMER/EMEA"), ("Ada", "EMEA"), ("Cy", "")])
+        );
+    }
+
+    #[test]
+    fn test_parse_list() {
+        assert_eq!(
+            parse_list("Ada;EMEA, Bo;AMER/EMEA, Cy").unwrap(),
+            nominations(&[("Ada", "EMEA"), ("Bo", "AMER/EMEA"), ("Cy", "")])
+        );
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let csv =
+            "name,regions\n\"Smith, Jo\",AMER/EMEA\n\"Ada \"\"Countess\"\" Lovelace\",EMEA\nCy\n";
+        assert_eq!(
+            parse_csv(csv).unwrap(),
+            nominations(&[
+                ("Smith, Jo", "AMER/EMEA"),
+                ("Ada \"Countess\" Lovelace", "EMEA"),
+                ("Cy", ""),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let json = r#"[
+            {"name": "Smith, Jo", "regions": ["AMER", "EMEA"]},
+            {"name": "Ada", "regions": "EMEA"},
+            {"name": "Cy"}
+        ]"#;
+        assert_eq!(
+            parse_json(json).unwrap(),
+            nominations(&[("Smith, Jo", "AMER/EMEA"), ("Ada", "EMEA"), ("Cy", "")])
+        );
+    }
+
+    #[test_case(
+        "Ada;EMEA\n;AMER\nada ; AMER\nBo",
+        &["Line 2: missing a name", "Line 3: ada is a duplicate of line 1"];
+        "lines"
+    )]
+    #[test_case(
+        "name,regions\nAda,EMEA,extra\n\"Bo,AMER\nAda,AMER",
+        &[
+            "Line 2: expected 2 columns, found 3",
+            "Line 3: unterminated quote",
+        ];
+        "csv"
+    )]
+    fn test_every_problem_is_reported(input: &str, problems: &[&str]) {
+        let parse = if input.starts_with("name,") {
+            parse_csv
+        } else {
+            parse_lines
+        };
+        let error = parse(input).unwrap_err().to_string();
+        for problem in problems {
+            assert!(error.contains(problem), "{error} should mention {problem}");
+        }
+    }
+
+    #[test]
+    fn test_json_errors_name_the_entry() {
+        let error = parse_json(r#"[{"name": "Ada"}, {"regions": "EMEA"}, {"name": " "}]"#)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Entry 2: missing field `name`"), "{error}");
+        assert!(error.contains("Entry 3: missing a name"), "{error}");
+    }
+
+    #[test_case("list.xlsx"; "unknown extension")]
+    #[test_case("list"; "no extension")]
+    fn test_parse_file_needs_a_known_format(filename: &str) {
+        assert!(parse_file(filename, b"Ada,EMEA").is_err());
+    }
+}
@@ -0,0 +1,217 @@
+//! The full result of counting an election, with enough detail to explain it.
+
+use std::collections::BTreeMap;
+
+use poise::{serenity_prelude as serenity, ChoiceParameter as _};
+
+use super::{Election, Name, Region, Regions, Seat, Tie};
+
+/// Why a candidate did or didn't get an office.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    Elected(Seat),
+    /// Passed over because they didn't have the election's minimum support.
+    Ineligible,
+    /// Withdrew from the election.
+    Withdrawn,
+    /// Passed over because every office left was reserved for another region.
+    NoOpenOffice {
+        reserved_for: Vec<Region>,
+    },
+    /// Passed over because their region already held as many offices as it may.
+    RegionFull {
+        region: Region,
+        max: usize,
+    },
+    /// Every office was filled before the count reached them, or they were eliminated.
+    NotElected,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Standing {
+    /// Position in the count, starting at 1.
+    pub rank: usize,
+    pub name: Name,
+    pub regions: Regions,
+    /// The score the candidate was ranked by. For Single Transferable Vote this is the number of
+    /// votes held when the candidate was elected or eliminated.
+    pub score: f32,
+    /// Number of voters who scored or approved the candidate.
+    pub votes: usize,
+    pub status: Status,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Outcome {
+    /// Every candidate, best placed first.
+    pub standings: Vec<Standing>,
+    pub ties: Vec<Tie>,
+    /// Reserved offices that were left empty, by region.
+    pub vacant: BTreeMap<Region, usize>,
+    /// Whether every office that wasn't left vacant was filled.
+    pub complete: bool,
+}
+
+impl Outcome {
+    /// The elected candidates sorted by name, or `None` if the election couldn't complete.
+    pub fn elected(&self) -> Option<Vec<Name>> {
+        if !self.complete {
+            return None;
+        }
+        let mut elected: Vec<_> = self
+            .standings
+            .iter()
+            .filter(|s| matches!(s.status, Status::Elected(_)))
+            .map(|s| s.name.clone())
+            .collect();
+        elected.sort();
+        Some(elected)
+    }
+
+    /// The winners, for posting publicly once voting has closed.
+    pub fn make_announcement(&self, election: &Election) -> serenity::CreateEmbed {
+        let mut lines: Vec<_> = self
+            .standings
+            .iter()
+            .filter_map(|s| match &s.status {
+                Status::Elected(seat) => {
+                    Some(format!("* {} ({seat} office)", election.mention(&s.name)))
+                }
+                _ => None,
+            })
+            .collect();
+        lines.extend(
+            self.vacant
+                .iter()
+                .map(|(region, n)| format!("* {n} reserved for {region} left vacant")),
+        );
+        let mut embed = serenity::CreateEmbed::new()
+            .title("Elected")
+            .color(if self.complete {
+                serenity::Color::GOLD
+            } else {
+                serenity::Color::RED
+            })
+            .description(lines.join("\n"));
+        if !self.complete {
+            embed = embed.field(
+                "Incomplete",
+                "There were not enough eligible candidates to fill every office.",
+                false,
+            );
+        }
+        embed
+    }
+
+    pub fn make_embed(&self, election: &Election) -> serenity::CreateEmbed {
+        let mut embed = serenity::CreateEmbed::new()
+            .title("Election results")
+            .color(if self.complete {
+                serenity::Color::GOLD
+            } else {
+                serenity::Color::RED
+            })
+            .description(
+                self.standings
+                    .iter()
+                    .map(|s| {
+                        let explanation = match &s.status {
+                            Status::Elected(seat) => format!("elected ({seat} office)"),
+                            Status::Ineligible => match election.min_support {
+                                Some(support) => format!("ineligible, needed {support}"),
+                                None => "ineligible".into(),
+                            },
+                            Status::Withdrawn => "withdrawn".into(),
+                            Status::NoOpenOffice { reserved_for } => format!(
+                                "skipped, the remaining offices were reserved for {}",
+                                reserved_for
+                                    .iter()
+                                    .map(|r| r.to_string())
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            ),
+                            Status::RegionFull { region, max } => {
+                                format!("skipped, {region} already held its maximum of {max}")
+                            }
+                            Status::NotElected => "not elected".into(),
+                        };
+                        format!(
+                            "{}. {} ({}): {:.2} from {} votes, {explanation}",
+                            s.rank,
+                            election.mention(&s.name),
+                            s.regions,
+                            s.score,
+                            s.votes
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            )
+            .field("Turnout", election.turnout(), true)
+            .field("Counting method", election.method.name(), true)
+            .field(
+                "Ballot log",
+                match election.verify_log() {
+                    Ok(()) => format!(
+                        "{} entries, ending `{}`",
+                        election.log_len(),
+                        election.log_head()
+                    ),
+                    Err(problem) => format!("⚠️ {problem}"),
+                },
+                false,
+            );
+
+        if !self.vacant.is_empty() {
+            embed = embed.field(
+                "Vacant offices",
+                self.vacant
+                    .iter()
+                    .map(|(region, n)| format!("* {n} reserved for {region}"))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                false,
+            );
+        }
+
+        if !self.complete {
+            embed = embed.field(
+                "Incomplete",
+                "There were not enough eligible candidates to fill every office.",
+                false,
+            );
+        }
+
+        if !self.ties.is_empty() {
+            embed = embed.field(
+                match election.tie_seed {
+                    Some(seed) => format!("Ties (seed {seed})"),
+                    None => "Ties".into(),
+                },
+                self.ties
+                    .iter()
+                    .map(|tie| {
+                        format!(
+                            "* {} at {:.2}, by {}",
+                            tie.candidates
+                                .iter()
+                                .map(|c| format!("**{c}**"))
+                                .collect::<Vec<_>>()
+                                .join(" ahead of "),
+                            tie.score,
+                            tie.resolved_by
+                                .iter()
+                                .map(|t| t.name().to_lowercase())
+                                .collect::<Vec<_>>()
+                                .join(" then "),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                false,
+            );
+        }
+
+        embed
+    }
+}
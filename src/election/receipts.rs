@@ -0,0 +1,111 @@
+//! Receipts voters can check their recorded ballot against.
+//!
+//! A receipt is a short HMAC of the election, the voter and their ballot under the ballot key.
+//! Nothing is stored: checking a receipt recomputes it from the ballot on record, so it only
+//! matches while that ballot is exactly the one the voter cast.
+
+use poise::serenity_prelude as serenity;
+
+use super::{Ballot, BallotKey, Election};
+
+/// Hex digits kept from the HMAC.
+const LENGTH: usize = 20;
+
+fn receipt(key: &BallotKey, election: usize, user: serenity::UserId, ballot: &Ballot) -> String {
+    let ballot = serde_json::to_string(ballot).unwrap_or_default();
+    let digits = key.sign(&format!("receipt:{election}:{user}:{ballot}"))[..LENGTH].to_uppercase();
+    digits
+        .as_bytes()
+        .chunks(4)
+        .map(|c| String::from_utf8_lossy(c).into_owned())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Drops the separators and case people may add or lose when copying a receipt.
+fn normalize(receipt: &str) -> String {
+    receipt
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .collect::<String>()
+        .to_uppercase()
+}
+
+impl Election {
+    /// The receipt for the ballot on record for `user` in election `id`, if they have voted.
+    pub fn receipt(
+        &self,
+        id: usize,
+        user: serenity::UserId,
+        key: &BallotKey,
+    ) -> Result<Option<String>, anyhow::Error> {
+        Ok(self
+            .ballot_of(user, Some(key))?
+            .map(|ballot| receipt(key, id, user, ballot)))
+    }
+
+    /// The ballot on record for `user`, if it is the one the receipt was issued for.
+    pub fn check_receipt(
+        &self,
+        id: usize,
+        user: serenity::UserId,
+        code: &str,
+        key: &BallotKey,
+    ) -> Result<Option<&Ballot>, anyhow::Error> {
+        Ok(self
+            .ballot_of(user, Some(key))?
+            .filter(|ballot| normalize(&receipt(key, id, user, ballot)) == normalize(code)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use chrono::Utc;
+    use test_case::test_case;
+
+    use super::*;
+
+    fn ballot(rank: usize) -> Ballot {
+        Ballot {
+            votes: BTreeMap::from([("a".into(), rank)]),
+        }
+    }
+
+    #[test_case(false; "open")]
+    #[test_case(true; "secret")]
+    fn test_receipt_matches_the_ballot_cast(secret: bool) {
+        let key = BallotKey::new(b"key");
+        let mut election = Election::new(1, 1);
+        election.add_candidate("a", "EMEA");
+        if secret {
+            election.make_secret();
+        }
+        election
+            .cast(2.into(), ballot(3), Some(&key), Utc::now())
+            .unwrap();
+        let code = election.receipt(7, 2.into(), &key).unwrap().unwrap();
+        assert_eq!(code.len(), LENGTH + LENGTH / 4 - 1);
+
+        let check = |election: &Election, user: u64, id, code: &str| {
+            election
+                .check_receipt(id, user.into(), code, &key)
+                .unwrap()
+                .cloned()
+        };
+        assert_eq!(check(&election, 2, 7, &code), Some(ballot(3)));
+        assert_eq!(
+            check(&election, 2, 7, &code.replace('-', " ").to_lowercase()),
+            Some(ballot(3))
+        );
+        assert_eq!(check(&election, 3, 7, &code), None);
+        assert_eq!(check(&election, 2, 8, &code), None);
+
+        // A ballot changed since the receipt was issued no longer matches it.
+        election
+            .cast(2.into(), ballot(4), Some(&key), Utc::now())
+            .unwrap();
+        assert_eq!(check(&election, 2, 7, &code), None);
+    }
+}
@@ -0,0 +1,154 @@
+//! Reminders sent to members of the voter roll who haven't voted as the close approaches.
+
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+use super::{Election, State};
+
+/// Parses hours before closing separated by commas, such as `24, 1`. An empty string means no
+/// reminders.
+pub fn parse_reminders(text: &str) -> Result<BTreeSet<u32>, anyhow::Error> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|h| !h.is_empty())
+        .map(|h| {
+            let hours = h
+                .trim_end_matches('h')
+                .parse::<u32>()
+                .ok()
+                .filter(|h| *h > 0);
+            hours.ok_or_else(|| anyhow::anyhow!("{h} should be a number of hours, such as 24"))
+        })
+        .collect()
+}
+
+impl Election {
+    pub fn reminders(&self) -> &BTreeSet<u32> {
+        &self.reminders
+    }
+
+    /// Replaces the reminder schedule. Reminders whose time has passed aren't sent late.
+    pub fn set_reminders(&mut self, hours: BTreeSet<u32>, now: DateTime<Utc>) {
+        self.reminders = hours;
+        self.reset_reminders(now);
+    }
+
+    /// Marks reminders that were due before `now` as sent, for when the closing time changes.
+    pub(super) fn reset_reminders(&mut self, now: DateTime<Utc>) {
+        self.reminded = match self.closes_at {
+            Some(closes_at) => self
+                .reminders
+                .iter()
+                .filter(|h| closes_at - TimeDelta::hours(i64::from(**h)) <= now)
+                .copied()
+                .collect(),
+            None => BTreeSet::new(),
+        };
+    }
+
+    /// Reminders due by `now` that haven't been sent, in hours before closing.
+    fn due(&self, now: DateTime<Utc>) -> Vec<u32> {
+        let Some(closes_at) = self.closes_at.filter(|_| self.state == State::Open) else {
+            return Vec::new();
+        };
+        self.reminders
+            .iter()
+            .filter(|h| {
+                !self.reminded.contains(h) && closes_at - TimeDelta::hours(i64::from(**h)) <= now
+            })
+            .copied()
+            .collect()
+    }
+
+    /// The reminder that is due, in hours before closing. When several are due at once only the
+    /// one closest to closing is returned. It stays due until `mark_reminded`.
+    pub fn due_reminder(&self, now: DateTime<Utc>) -> Option<u32> {
+        self.due(now).into_iter().min()
+    }
+
+    /// Records that the reminders due by `now` were sent.
+    pub fn mark_reminded(&mut self, now: DateTime<Utc>) {
+        let due = self.due(now);
+        self.reminded.extend(due);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_case::test_case;
+
+    fn time(hours: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(0, 0).unwrap() + TimeDelta::hours(hours)
+    }
+
+    fn take_due_reminder(election: &mut Election, now: DateTime<Utc>) -> Option<u32> {
+        let hours = election.due_reminder(now);
+        election.mark_reminded(now);
+        hours
+    }
+
+    fn election() -> Election {
+        let mut election = Election::new(1, 1);
+        election
+            .set_schedule(None, Some(time(100)), time(0))
+            .unwrap();
+        election.set_reminders(BTreeSet::from([24, 1]), time(0));
+        election
+    }
+
+    #[test_case("24, 1", Some(vec![1, 24]); "hours")]
+    #[test_case("48h,2h", Some(vec![2, 48]); "suffix")]
+    #[test_case("", Some(vec![]); "none")]
+    #[test_case("24, soon", None; "not a number")]
+    #[test_case("0", None; "zero")]
+    fn test_parse_reminders(text: &str, expected: Option<Vec<u32>>) {
+        assert_eq!(
+            parse_reminders(text).ok(),
+            expected.map(|h| h.into_iter().collect())
+        );
+    }
+
+    #[test]
+    fn test_reminders_are_sent_once() {
+        let mut election = election();
+        assert_eq!(take_due_reminder(&mut election, time(50)), None);
+        assert_eq!(take_due_reminder(&mut election, time(76)), Some(24));
+        assert_eq!(take_due_reminder(&mut election, time(77)), None);
+        assert_eq!(take_due_reminder(&mut election, time(99)), Some(1));
+        assert_eq!(take_due_reminder(&mut election, time(99)), None);
+    }
+
+    #[test]
+    fn test_reminders_stay_due_until_sent() {
+        let election = election();
+        assert_eq!(election.due_reminder(time(76)), Some(24));
+        assert_eq!(election.due_reminder(time(77)), Some(24));
+    }
+
+    #[test]
+    fn test_missed_reminders_collapse() {
+        let mut election = election();
+        assert_eq!(take_due_reminder(&mut election, time(99)), Some(1));
+        assert_eq!(take_due_reminder(&mut election, time(99)), None);
+    }
+
+    #[test]
+    fn test_reopening_schedules_reminders_again() {
+        let mut election = election();
+        assert_eq!(take_due_reminder(&mut election, time(99)), Some(1));
+        election.close();
+        assert_eq!(take_due_reminder(&mut election, time(99)), None);
+        election.reopen(Some(time(200)), time(101)).unwrap();
+        assert_eq!(take_due_reminder(&mut election, time(176)), Some(24));
+    }
+
+    #[test]
+    fn test_past_reminders_are_skipped() {
+        let mut election = election();
+        election.set_reminders(BTreeSet::from([48, 24, 1]), time(80));
+        assert_eq!(take_due_reminder(&mut election, time(80)), None);
+        assert_eq!(take_due_reminder(&mut election, time(99)), Some(1));
+    }
+}
@@ -0,0 +1,160 @@
+//! Fixed electorates and turnout.
+//!
+//! An election may have a voter roll, either uploaded by the owner or snapshotted from a role's
+//! members when voting opens. Only members on the roll can vote, and turnout is reported against
+//! it.
+
+use std::collections::BTreeSet;
+
+use poise::serenity_prelude as serenity;
+
+use super::{Election, State};
+
+/// Parses user IDs or mentions separated by commas, spaces or new lines, reporting every entry
+/// that isn't one.
+pub fn parse_roll(text: &str) -> Result<BTreeSet<serenity::UserId>, anyhow::Error> {
+    let mut roll = BTreeSet::new();
+    let mut errors = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        for entry in line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|e| !e.is_empty())
+        {
+            let id = entry
+                .strip_prefix("<@")
+                .and_then(|e| e.strip_suffix('>'))
+                .map(|e| e.trim_start_matches('!'))
+                .unwrap_or(entry);
+            match id.parse::<u64>() {
+                Ok(id) if id > 0 => {
+                    roll.insert(serenity::UserId::new(id));
+                }
+                _ => errors.push(format!("Line {}: {entry} is not a user ID", i + 1)),
+            }
+        }
+    }
+    if !errors.is_empty() {
+        anyhow::bail!("The voter roll has problems:\n{}", errors.join("\n"));
+    }
+    if roll.is_empty() {
+        anyhow::bail!("The voter roll is empty");
+    }
+    Ok(roll)
+}
+
+impl Election {
+    pub fn roll(&self) -> Option<&BTreeSet<serenity::UserId>> {
+        self.roll.as_ref()
+    }
+
+    /// Replaces the voter roll. `None` lets anyone who meets the eligibility rules vote.
+    pub fn set_roll(&mut self, roll: Option<BTreeSet<serenity::UserId>>) {
+        self.roll = roll;
+        self.roll_role = None;
+    }
+
+    /// Fills the voter roll with the role's members when voting opens.
+    pub fn set_roll_role(&mut self, role: serenity::RoleId) {
+        self.roll = None;
+        self.roll_role = Some(role);
+    }
+
+    /// The role whose members should be snapshotted into the roll now, if any.
+    pub fn pending_snapshot(&self) -> Option<serenity::RoleId> {
+        match (self.state, &self.roll) {
+            (State::Draft, _) | (_, Some(_)) => None,
+            _ => self.roll_role,
+        }
+    }
+
+    /// Records the members of the roll role at the time voting opened.
+    pub fn snapshot_roll(&mut self, members: BTreeSet<serenity::UserId>) {
+        self.roll = Some(members);
+    }
+
+    /// Whether `user` is on the roll, or the election has none. Nobody is on a roll that is
+    /// waiting for its snapshot.
+    pub fn on_roll(&self, user: serenity::UserId) -> bool {
+        match (&self.roll, self.roll_role) {
+            (Some(roll), _) => roll.contains(&user),
+            (None, Some(_)) => false,
+            (None, None) => true,
+        }
+    }
+
+    /// Members on the roll who haven't voted, or `None` if there is no roll.
+    pub fn non_voters(&self) -> Option<Vec<serenity::UserId>> {
+        let roll = self.roll.as_ref()?;
+        Some(
+            roll.iter()
+                .filter(|u| !self.has_voted(**u))
+                .copied()
+                .collect(),
+        )
+    }
+
+    /// How many have voted, out of the roll if there is one.
+    pub fn turnout(&self) -> String {
+        match &self.roll {
+            Some(roll) => format!(
+                "{} of {} eligible voters",
+                roll.iter().filter(|u| self.has_voted(**u)).count(),
+                roll.len()
+            ),
+            None => format!("{}", self.ballot_count()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn users(ids: &[u64]) -> BTreeSet<serenity::UserId> {
+        ids.iter().map(|id| serenity::UserId::new(*id)).collect()
+    }
+
+    #[test]
+    fn test_parse_roll() {
+        assert_eq!(
+            parse_roll("1, 2\n<@3> <@!4>\n\n5").unwrap(),
+            users(&[1, 2, 3, 4, 5])
+        );
+        let error = parse_roll("1\nbob, 2 @carol").unwrap_err().to_string();
+        assert!(error.contains("Line 2: bob is not"), "{error}");
+        assert!(error.contains("Line 2: @carol is not"), "{error}");
+        assert!(parse_roll(" \n").is_err());
+    }
+
+    #[test]
+    fn test_turnout() {
+        let mut election = Election::new(1, 1);
+        election.add_candidate("a", "EMEA");
+        election.vote(2.into(), "a", 5);
+        election.vote(5.into(), "a", 5);
+        assert_eq!(election.turnout(), "2");
+        assert_eq!(election.non_voters(), None);
+        assert!(election.on_roll(9.into()));
+
+        election.set_roll(Some(users(&[2, 3, 4])));
+        assert_eq!(election.turnout(), "1 of 3 eligible voters");
+        assert_eq!(election.non_voters(), Some(vec![3.into(), 4.into()]));
+        assert!(!election.on_roll(5.into()));
+    }
+
+    #[test]
+    fn test_snapshot_waits_for_voting_to_open() {
+        let mut election = Election::new(1, 1);
+        election.set_roll_role(7.into());
+        assert_eq!(election.pending_snapshot(), Some(7.into()));
+        assert!(!election.on_roll(2.into()));
+        election.state = State::Draft;
+        assert_eq!(election.pending_snapshot(), None);
+        election.state = State::Open;
+        election.snapshot_roll(users(&[2]));
+        assert_eq!(election.pending_snapshot(), None);
+        assert!(election.on_roll(2.into()));
+        election.set_roll(None);
+        assert_eq!(election.pending_snapshot(), None);
+    }
+}
@@ -0,0 +1,148 @@
+//! Schulze (beatpath) ranking.
+//!
+//! Every ballot is read as a set of pairwise preferences: a candidate with a higher rank is
+//! preferred over one with a lower rank, and abstentions are treated as unranked, below every
+//! ranked candidate. Two unranked candidates are not compared against each other.
+
+use std::collections::BTreeMap;
+
+use super::{Election, Name};
+
+/// Number of voters preferring the row candidate over the column candidate.
+pub(super) fn pairwise(election: &Election, names: &[&Name]) -> Vec<Vec<usize>> {
+    let mut d = vec![vec![0; names.len()]; names.len()];
+    for ballot in election.cast_ballots() {
+        let ranks: Vec<usize> = names
+            .iter()
+            .map(|n| ballot.votes.get(*n).copied().unwrap_or(0))
+            .collect();
+        for (i, a) in ranks.iter().enumerate() {
+            for (j, b) in ranks.iter().enumerate() {
+                if a > b {
+                    d[i][j] += 1;
+                }
+            }
+        }
+    }
+    d
+}
+
+/// Strength of the strongest path between every pair of candidates.
+fn strongest_paths(d: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let n = d.len();
+    let mut p = vec![vec![0; n]; n];
+    for i in 0..n {
+        for j in 0..n {
+            if i != j && d[i][j] > d[j][i] {
+                p[i][j] = d[i][j];
+            }
+        }
+    }
+    for k in 0..n {
+        for i in 0..n {
+            for j in 0..n {
+                if i != j && i != k && j != k {
+                    p[i][j] = p[i][j].max(p[i][k].min(p[k][j]));
+                }
+            }
+        }
+    }
+    p
+}
+
+/// Scores candidates by how many others they beat on strongest paths.
+pub(super) fn rank(election: &Election) -> Vec<(f32, Name)> {
+    let names: Vec<&Name> = election.candidates.keys().collect();
+    let p = strongest_paths(&pairwise(election, &names));
+    let wins: BTreeMap<&Name, usize> = names
+        .iter()
+        .enumerate()
+        .map(|(i, n)| {
+            let wins = (0..names.len()).filter(|&j| p[i][j] > p[j][i]).count();
+            (*n, wins)
+        })
+        .collect();
+
+    wins.into_iter()
+        .map(|(n, wins)| (wins as f32, n.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::election::CountingMethod;
+    use test_case::test_case;
+
+    fn election(ballots: Vec<Vec<(&str, usize)>>) -> Election {
+        let mut election = Election::new(1, 1);
+        election.set_method(CountingMethod::Schulze);
+        for n in ["a", "b", "c", "d", "e"] {
+            election.add_candidate(n, "EMEA");
+        }
+        for (i, ballot) in ballots.into_iter().enumerate() {
+            for (n, rank) in ballot {
+                election.vote((i as u64 + 1).into(), n, rank);
+            }
+        }
+        election.close();
+        election
+    }
+
+    /// Builds `count` ballots ranking the candidates in the order given.
+    fn ranked(groups: Vec<(usize, &str)>) -> Vec<Vec<(&str, usize)>> {
+        groups
+            .into_iter()
+            .flat_map(|(count, order)| {
+                let ballot: Vec<_> = order
+                    .split(' ')
+                    .enumerate()
+                    .map(|(i, n)| (n, 5 - i))
+                    .collect();
+                std::iter::repeat_n(ballot, count)
+            })
+            .collect()
+    }
+
+    #[test_case(
+        vec![(1, "a"), (3, "b a")],
+        "b";
+        "broad support beats one enthusiast"
+    )]
+    #[test_case(
+        vec![
+            (5, "a c b e d"),
+            (5, "a d e c b"),
+            (8, "b e d a c"),
+            (3, "c a b e d"),
+            (7, "c a e b d"),
+            (2, "c b a d e"),
+            (7, "d c e b a"),
+            (8, "e b a d c"),
+        ],
+        "e";
+        "beatpath cycle"
+    )]
+    fn test_schulze_winner(ballots: Vec<(usize, &str)>, expected: &str) {
+        let election = election(ranked(ballots));
+        let (ranking, _) = election.break_ties(rank(&election));
+        assert_eq!(ranking.last().map(|(_, n)| n), Some(&expected.into()));
+    }
+
+    #[test]
+    fn test_abstentions_are_unranked() {
+        let election = election(vec![
+            vec![("a", 2), ("b", 0), ("c", 0)],
+            vec![("a", 0), ("b", 3), ("c", 0)],
+            vec![("a", 0), ("b", 3), ("c", 1)],
+        ]);
+        let names: Vec<&Name> = election.candidates.keys().collect();
+        let d = pairwise(&election, &names);
+        // a over b only on the first ballot, b over a on the other two.
+        assert_eq!((d[0][1], d[1][0]), (1, 2));
+        // Neither c nor d is ranked on the first two ballots.
+        assert_eq!((d[2][3], d[3][2]), (1, 0));
+        let (ranking, _) = election.break_ties(rank(&election));
+        assert_eq!(ranking.last().map(|(_, n)| n), Some(&"b".into()));
+    }
+}
@@ -0,0 +1,452 @@
+//! Regional quotas and the bookkeeping used to respect them while offices are filled.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{Election, Name, Region, Regions, Status};
+
+/// How many offices a region must and may hold.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quota {
+    /// Offices reserved for candidates from the region.
+    pub min: usize,
+    /// The most offices candidates from the region may hold, if limited.
+    pub max: Option<usize>,
+}
+
+impl std::fmt::Display for Quota {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.min, self.max) {
+            (min, Some(max)) if min == max => write!(f, "exactly {min}"),
+            (0, Some(max)) => write!(f, "at most {max}"),
+            (min, Some(max)) => write!(f, "at least {min}, at most {max}"),
+            (min, None) => write!(f, "at least {min}"),
+        }
+    }
+}
+
+/// Parses a region with an optional quota.
+///
+/// `EMEA` reserves one office, `EMEA:2` reserves two, `EMEA:1-3` reserves one and allows at
+/// most three, and `EMEA:0-2` only caps the region at two.
+pub fn parse_quota(s: &str) -> Result<(Region, Quota), anyhow::Error> {
+    let Some((region, quota)) = s.split_once(':') else {
+        return Ok((s.trim().into(), Quota { min: 1, max: None }));
+    };
+    let region = region.trim();
+    if region.is_empty() {
+        anyhow::bail!("Quota {s} is missing a region");
+    }
+    let quota = match quota.split_once('-') {
+        Some((min, max)) => Quota {
+            min: min.trim().parse()?,
+            max: Some(max.trim().parse()?),
+        },
+        None => Quota {
+            min: quota.trim().parse()?,
+            max: None,
+        },
+    };
+    Ok((region.into(), quota))
+}
+
+/// What happens to reserved offices when a region doesn't have enough eligible candidates.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, poise::ChoiceParameter,
+)]
+pub enum VacancyPolicy {
+    /// Keep the reservation, so the election can't complete.
+    #[default]
+    #[name = "Fail the election"]
+    Fail,
+
+    /// Leave the office empty and fill the rest.
+    #[name = "Leave vacant"]
+    LeaveVacant,
+
+    /// Let a candidate from any region take the office.
+    #[name = "Release to general pool"]
+    Release,
+}
+
+/// The kind of office a candidate was seated in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Seat {
+    Reserved(Region),
+    Unreserved,
+}
+
+impl std::fmt::Display for Seat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Seat::Reserved(region) => write!(f, "{region} reserved"),
+            Seat::Unreserved => write!(f, "unreserved"),
+        }
+    }
+}
+
+/// Tracks the offices that are still open while an election is being counted.
+///
+/// Candidates who cover several regions aren't tied to one reservation when they're seated.
+/// Every time someone is seated the winners are matched to offices again, so a later winner
+/// can take a reservation an earlier winner was holding if the earlier one can move to another.
+#[derive(Debug, Clone)]
+pub(super) struct Seats {
+    open: usize,
+    quotas: BTreeMap<Region, Quota>,
+    elected: Vec<(Name, Regions)>,
+    /// The office each elected candidate holds, in the order they were elected.
+    seated: Vec<Seat>,
+    /// Offices left empty up front because their region had too few eligible candidates.
+    vacant: BTreeMap<Region, usize>,
+}
+
+impl Seats {
+    /// Applies the election's vacancy policy to reservations that can't be met by its eligible
+    /// candidates.
+    pub(super) fn new(election: &Election) -> Seats {
+        let ineligible = election.ineligible();
+        let mut seats = Seats {
+            open: election.offices,
+            quotas: election.quotas.clone(),
+            elected: Vec::new(),
+            seated: Vec::new(),
+            vacant: BTreeMap::new(),
+        };
+        if election.vacancy_policy == VacancyPolicy::Fail {
+            return seats;
+        }
+        let eligible: Vec<&Regions> = election
+            .candidates
+            .iter()
+            .filter(|(n, _)| !ineligible.contains(*n))
+            .map(|(_, c)| &c.regions)
+            .collect();
+        let fillable = reserved(seats.matching(&eligible).iter().flatten());
+        for (region, quota) in &mut seats.quotas {
+            let fillable = fillable.get(region).copied().unwrap_or(0);
+            if fillable >= quota.min {
+                continue;
+            }
+            let shortfall = quota.min - fillable;
+            quota.min = fillable;
+            if election.vacancy_policy == VacancyPolicy::LeaveVacant {
+                seats.open -= shortfall;
+                seats.vacant.insert(region.clone(), shortfall);
+            }
+        }
+        seats
+    }
+
+    pub(super) fn remaining(&self) -> usize {
+        self.open
+    }
+
+    /// Number of open offices reserved for `region`.
+    pub(super) fn reserved_for(&self, region: &Region) -> usize {
+        let min = self.quotas.get(region).map(|q| q.min).unwrap_or(0);
+        let filled = reserved(&self.seated).get(region).copied().unwrap_or(0);
+        min.saturating_sub(filled)
+    }
+
+    /// Offices that will stay empty, by the region they were reserved for.
+    pub(super) fn vacancies(&self) -> BTreeMap<Region, usize> {
+        let mut vacant = self.vacant.clone();
+        for region in self.quotas.keys() {
+            let unfilled = self.reserved_for(region);
+            if unfilled > 0 {
+                *vacant.entry(region.clone()).or_default() += unfilled;
+            }
+        }
+        vacant
+    }
+
+    /// The office each elected candidate ended up holding.
+    pub(super) fn seated(&self) -> BTreeMap<Name, Seat> {
+        self.elected
+            .iter()
+            .map(|(n, _)| n.clone())
+            .zip(self.seated.iter().cloned())
+            .collect()
+    }
+
+    /// Matches candidates to offices, filling as many reserved offices as possible before
+    /// placing the rest in unreserved offices without passing any region's maximum. Candidates
+    /// who can't be placed get `None`.
+    fn matching(&self, candidates: &[&Regions]) -> Vec<Option<Seat>> {
+        let unlimited = candidates.len();
+        let mut slots = Vec::new();
+        for region in candidates.iter().flat_map(|r| r.iter()) {
+            if !self.quotas.contains_key(region) && !slots.iter().any(|(r, _)| r == region) {
+                slots.extend(std::iter::repeat_n((region.clone(), false), unlimited));
+            }
+        }
+        for (region, quota) in &self.quotas {
+            let extra = quota.max.map_or(unlimited, |max| max - quota.min);
+            slots.extend(std::iter::repeat_n((region.clone(), true), quota.min));
+            slots.extend(std::iter::repeat_n((region.clone(), false), extra));
+        }
+
+        let mut holder: Vec<Option<usize>> = vec![None; slots.len()];
+        // Reserved offices first. Augmenting paths never empty a filled office, so the second pass
+        // keeps every reservation the first pass filled.
+        for reserved_only in [true, false] {
+            let usable = |c: usize, s: usize| {
+                let (region, reserved) = &slots[s];
+                candidates[c].contains(region) && (*reserved || !reserved_only)
+            };
+            for c in 0..candidates.len() {
+                if !holder.contains(&Some(c)) {
+                    augment(c, &usable, &mut holder, &mut vec![false; slots.len()]);
+                }
+            }
+        }
+
+        (0..candidates.len())
+            .map(|c| {
+                if candidates[c].is_empty() {
+                    return Some(Seat::Unreserved);
+                }
+                let s = holder.iter().position(|h| *h == Some(c))?;
+                Some(match &slots[s] {
+                    (region, true) => Seat::Reserved(region.clone()),
+                    (_, false) => Seat::Unreserved,
+                })
+            })
+            .collect()
+    }
+
+    /// The winners' offices if a candidate covering `regions` were elected next.
+    fn seat_with(&self, regions: &Regions) -> Result<Vec<Seat>, Status> {
+        let mut candidates: Vec<&Regions> = self.elected.iter().map(|(_, r)| r).collect();
+        candidates.push(regions);
+        let Some(seated) = self
+            .matching(&candidates)
+            .into_iter()
+            .collect::<Option<Vec<_>>>()
+        else {
+            let (region, max) = regions
+                .iter()
+                .find_map(|r| Some((r.clone(), self.quotas.get(r)?.max?)))
+                .expect("only capped regions can run out of offices");
+            return Err(Status::RegionFull { region, max });
+        };
+        let unmet: usize = self
+            .quotas
+            .iter()
+            .map(|(r, q)| {
+                q.min
+                    .saturating_sub(reserved(&seated).get(r).copied().unwrap_or(0))
+            })
+            .sum();
+        if unmet >= self.open {
+            return Err(Status::NoOpenOffice {
+                reserved_for: self
+                    .quotas
+                    .keys()
+                    .filter(|r| self.reserved_for(r) > 0)
+                    .cloned()
+                    .collect(),
+            });
+        }
+        Ok(seated)
+    }
+
+    /// The office a candidate covering `regions` would take, or why they can't take one.
+    pub(super) fn check(&self, regions: &Regions) -> Result<Seat, Status> {
+        let mut seated = self.seat_with(regions)?;
+        Ok(seated.pop().expect("the candidate was seated"))
+    }
+
+    /// Seats a candidate, moving earlier winners between offices if that fills more
+    /// reservations.
+    pub(super) fn take(&mut self, name: &Name, regions: &Regions) -> Result<Seat, Status> {
+        let seated = self.seat_with(regions)?;
+        self.open -= 1;
+        self.elected.push((name.clone(), regions.clone()));
+        self.seated = seated;
+        Ok(self
+            .seated
+            .last()
+            .cloned()
+            .expect("the candidate was seated"))
+    }
+}
+
+/// Number of reserved offices held per region.
+fn reserved<'a>(seats: impl IntoIterator<Item = &'a Seat>) -> BTreeMap<Region, usize> {
+    let mut reserved = BTreeMap::new();
+    for seat in seats {
+        if let Seat::Reserved(region) = seat {
+            *reserved.entry(region.clone()).or_default() += 1;
+        }
+    }
+    reserved
+}
+
+/// Looks for an augmenting path from candidate `c` (Kuhn's algorithm).
+fn augment(
+    c: usize,
+    usable: &dyn Fn(usize, usize) -> bool,
+    holder: &mut [Option<usize>],
+    visited: &mut [bool],
+) -> bool {
+    for s in 0..holder.len() {
+        if visited[s] || !usable(c, s) {
+            continue;
+        }
+        visited[s] = true;
+        let free = match holder[s] {
+            None => true,
+            Some(other) => augment(other, usable, holder, visited),
+        };
+        if free {
+            holder[s] = Some(c);
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("EMEA", ("EMEA", 1, None); "single reservation")]
+    #[test_case(" APAC : 2 ", ("APAC", 2, None); "minimum")]
+    #[test_case("AMER:1-3", ("AMER", 1, Some(3)); "range")]
+    #[test_case("AMER:0-2", ("AMER", 0, Some(2)); "cap only")]
+    fn test_parse_quota(input: &str, expected: (&str, usize, Option<usize>)) {
+        let (region, min, max) = expected;
+        assert_eq!(
+            parse_quota(input).unwrap(),
+            (region.into(), Quota { min, max })
+        );
+    }
+
+    #[test_case(":2"; "no region")]
+    #[test_case("EMEA:two"; "not a number")]
+    fn test_parse_quota_errors(input: &str) {
+        assert!(parse_quota(input).is_err());
+    }
+
+    #[test]
+    fn test_seats_respect_quotas() {
+        let mut election = Election::new(1, 4);
+        election
+            .set_quota(
+                "EMEA",
+                Quota {
+                    min: 0,
+                    max: Some(2),
+                },
+            )
+            .unwrap();
+        election
+            .set_quota("AMER", Quota { min: 2, max: None })
+            .unwrap();
+
+        let mut seats = Seats::new(&election);
+        assert_eq!(take(&mut seats, "EMEA"), Ok(Seat::Unreserved));
+        assert_eq!(take(&mut seats, "EMEA"), Ok(Seat::Unreserved));
+        assert_eq!(
+            take(&mut seats, "EMEA"),
+            Err(Status::RegionFull {
+                region: "EMEA".into(),
+                max: 2
+            })
+        );
+        assert_eq!(
+            take(&mut seats, "APAC"),
+            Err(Status::NoOpenOffice {
+                reserved_for: vec!["AMER".into()]
+            })
+        );
+        assert_eq!(take(&mut seats, "AMER"), Ok(Seat::Reserved("AMER".into())));
+        assert_eq!(take(&mut seats, "AMER"), Ok(Seat::Reserved("AMER".into())));
+        assert_eq!(seats.remaining(), 0);
+    }
+
+    /// Seats a candidate named after the order they were elected in.
+    fn take(seats: &mut Seats, regions: &str) -> Result<Seat, Status> {
+        let name = Name::from(format!("{}", seats.elected.len()));
+        seats.take(&name, &regions.into())
+    }
+
+    #[test]
+    fn test_multi_region_winner_moves_to_fill_reservations() {
+        let mut election = Election::new(1, 2);
+        election.reserve_office("AMER");
+        election.reserve_office("EMEA");
+
+        let mut seats = Seats::new(&election);
+        assert_eq!(
+            seats.take(&"a".into(), &"AMER/EMEA".into()),
+            Ok(Seat::Reserved("AMER".into()))
+        );
+        assert_eq!(
+            seats.take(&"b".into(), &"AMER".into()),
+            Ok(Seat::Reserved("AMER".into()))
+        );
+        assert_eq!(
+            seats.seated(),
+            BTreeMap::from([
+                ("a".into(), Seat::Reserved("EMEA".into())),
+                ("b".into(), Seat::Reserved("AMER".into())),
+            ])
+        );
+        assert!(seats.vacancies().is_empty());
+    }
+
+    #[test]
+    fn test_candidates_without_a_region() {
+        let mut election = Election::new(1, 2);
+        election.reserve_office("AMER");
+
+        let mut seats = Seats::new(&election);
+        assert_eq!(take(&mut seats, ""), Ok(Seat::Unreserved));
+        assert_eq!(
+            take(&mut seats, ""),
+            Err(Status::NoOpenOffice {
+                reserved_for: vec!["AMER".into()]
+            })
+        );
+        assert_eq!(
+            take(&mut seats, "AMER/EMEA"),
+            Ok(Seat::Reserved("AMER".into()))
+        );
+    }
+
+    #[test_case(VacancyPolicy::Fail, 2, 1, vec![("APAC", 1)]; "fail")]
+    #[test_case(VacancyPolicy::LeaveVacant, 2, 0, vec![("APAC", 1)]; "leave vacant")]
+    #[test_case(VacancyPolicy::Release, 3, 0, vec![]; "release")]
+    fn test_vacancy_policy(
+        policy: VacancyPolicy,
+        elected: usize,
+        remaining: usize,
+        vacant: Vec<(&str, usize)>,
+    ) {
+        let mut election = Election::new(1, 3);
+        election.set_vacancy_policy(policy);
+        election
+            .set_quota("APAC", Quota { min: 2, max: None })
+            .unwrap();
+        for (n, r) in [("a", "APAC"), ("b", "EMEA"), ("c", "EMEA")] {
+            election.add_candidate(n, r);
+        }
+
+        let mut seats = Seats::new(&election);
+        let taken = ["APAC", "EMEA", "EMEA"]
+            .into_iter()
+            .filter(|r| take(&mut seats, r).is_ok())
+            .count();
+        assert_eq!(taken, elected);
+        assert_eq!(seats.remaining(), remaining);
+        assert_eq!(
+            seats.vacancies(),
+            vacant.into_iter().map(|(r, n)| (r.into(), n)).collect()
+        );
+    }
+}
@@ -0,0 +1,278 @@
+//! Secret ballots.
+//!
+//! A secret election stores who has voted apart from what was voted. Ballots are keyed by a
+//! token: an HMAC of the voter's ID under a key kept outside the data files, salted per election.
+//! The bot can find a voter's ballot again to replace or void it, but the data files and their
+//! backups alone don't link any ballot to a voter.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude as serenity;
+use rand::prelude::*;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+
+use super::{ledger::Event, Ballot, Election};
+
+/// The key ballot tokens are derived with.
+pub struct BallotKey(hmac::Key);
+
+impl BallotKey {
+    /// The environment variable holding the key.
+    pub const VAR: &'static str = "BALLOT_KEY";
+
+    pub fn new(secret: &[u8]) -> BallotKey {
+        BallotKey(hmac::Key::new(hmac::HMAC_SHA256, secret))
+    }
+
+    /// Reads the key from `BALLOT_KEY`, if it is set.
+    pub fn from_env() -> Option<BallotKey> {
+        let secret = std::env::var(BallotKey::VAR).ok()?;
+        (!secret.is_empty()).then(|| BallotKey::new(secret.as_bytes()))
+    }
+
+    /// The HMAC of `message` in hex.
+    pub(super) fn sign(&self, message: &str) -> String {
+        let tag = hmac::sign(&self.0, message.as_bytes());
+        tag.as_ref().iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    fn token(&self, salt: &str, user: serenity::UserId) -> String {
+        self.sign(&format!("{salt}:{user}"))
+    }
+}
+
+/// The ballots of a secret election.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(super) struct SecretBallots {
+    salt: String,
+    /// Everyone who has voted, in no particular relation to `ballots`.
+    voted: BTreeSet<serenity::UserId>,
+    /// Ballots by token.
+    ballots: BTreeMap<String, Ballot>,
+}
+
+impl SecretBallots {
+    pub(super) fn ballots(&self) -> impl Iterator<Item = (String, Ballot)> + '_ {
+        self.ballots.iter().map(|(t, b)| (t.clone(), b.clone()))
+    }
+
+    pub(super) fn voted_count(&self) -> usize {
+        self.voted.len()
+    }
+}
+
+impl Election {
+    pub fn is_secret(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    /// Switches to secret ballots. Returns false if ballots have already been cast in the open.
+    pub fn make_secret(&mut self) -> bool {
+        if !self.ballots.is_empty() {
+            return false;
+        }
+        let salt: [u8; 16] = rand::thread_rng().gen();
+        self.secret.get_or_insert_with(|| SecretBallots {
+            salt: salt.iter().map(|b| format!("{b:02x}")).collect(),
+            ..Default::default()
+        });
+        true
+    }
+
+    /// Every ballot cast, however it is stored.
+    pub fn cast_ballots(&self) -> impl Iterator<Item = &Ballot> {
+        self.ballots
+            .values()
+            .chain(self.secret.iter().flat_map(|s| s.ballots.values()))
+    }
+
+    pub(super) fn cast_ballots_mut(&mut self) -> impl Iterator<Item = &mut Ballot> {
+        self.ballots
+            .values_mut()
+            .chain(self.secret.iter_mut().flat_map(|s| s.ballots.values_mut()))
+    }
+
+    pub fn ballot_count(&self) -> usize {
+        match &self.secret {
+            Some(secret) => secret.ballots.len(),
+            None => self.ballots.len(),
+        }
+    }
+
+    pub fn has_voted(&self, user: serenity::UserId) -> bool {
+        match &self.secret {
+            Some(secret) => secret.voted.contains(&user),
+            None => self.ballots.contains_key(&user),
+        }
+    }
+
+    fn secret_key(key: Option<&BallotKey>) -> Result<&BallotKey, anyhow::Error> {
+        key.ok_or_else(|| {
+            anyhow::anyhow!("Secret ballots need {} to be configured", BallotKey::VAR)
+        })
+    }
+
+    /// The ballot `user` cast, if any.
+    pub fn ballot_of(
+        &self,
+        user: serenity::UserId,
+        key: Option<&BallotKey>,
+    ) -> Result<Option<&Ballot>, anyhow::Error> {
+        match &self.secret {
+            Some(secret) => {
+                let token = Election::secret_key(key)?.token(&secret.salt, user);
+                Ok(secret.ballots.get(&token))
+            }
+            None => Ok(self.ballots.get(&user)),
+        }
+    }
+
+    /// Records `user`'s ballot, replacing any they cast before. Votes for names that are no
+    /// longer candidates, removed while the ballot was being filled in, are dropped.
+    pub fn cast(
+        &mut self,
+        user: serenity::UserId,
+        mut ballot: Ballot,
+        key: Option<&BallotKey>,
+        now: DateTime<Utc>,
+    ) -> Result<(), anyhow::Error> {
+        ballot
+            .votes
+            .retain(|name, _| self.candidates.contains_key(name));
+        let (voter, replaced) = match &mut self.secret {
+            Some(secret) => {
+                let token = Election::secret_key(key)?.token(&secret.salt, user);
+                secret.voted.insert(user);
+                let replaced = secret.ballots.insert(token.clone(), ballot.clone());
+                (token, replaced.is_some())
+            }
+            None => {
+                let replaced = self.ballots.insert(user, ballot.clone());
+                (user.to_string(), replaced.is_some())
+            }
+        };
+        let event = if replaced {
+            Event::Replaced { voter, ballot }
+        } else {
+            Event::Cast { voter, ballot }
+        };
+        self.record(event, now);
+        Ok(())
+    }
+
+    /// Removes `user`'s ballot. Returns whether they had one.
+    pub fn void(
+        &mut self,
+        user: serenity::UserId,
+        key: Option<&BallotKey>,
+        now: DateTime<Utc>,
+    ) -> Result<bool, anyhow::Error> {
+        let (voter, voided) = match &mut self.secret {
+            Some(secret) => {
+                let token = Election::secret_key(key)?.token(&secret.salt, user);
+                secret.voted.remove(&user);
+                let voided = secret.ballots.remove(&token).is_some();
+                (token, voided)
+            }
+            None => (user.to_string(), self.ballots.remove(&user).is_some()),
+        };
+        if voided {
+            self.record(Event::Voided { voter }, now);
+        }
+        Ok(voided)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ballot(rank: usize) -> Ballot {
+        Ballot {
+            votes: BTreeMap::from([("a".into(), rank)]),
+        }
+    }
+
+    fn secret_election() -> Election {
+        let mut election = Election::new(1, 1);
+        election.add_candidate("a", "EMEA");
+        assert!(election.make_secret());
+        election
+    }
+
+    #[test]
+    fn test_secret_ballots_can_be_replaced_and_voided() {
+        let key = BallotKey::new(b"key");
+        let mut election = secret_election();
+        election
+            .cast(2.into(), ballot(1), Some(&key), Utc::now())
+            .unwrap();
+        election
+            .cast(3.into(), ballot(2), Some(&key), Utc::now())
+            .unwrap();
+        election
+            .cast(2.into(), ballot(5), Some(&key), Utc::now())
+            .unwrap();
+
+        assert_eq!(election.ballot_count(), 2);
+        assert!(election.has_voted(2.into()));
+        assert_eq!(
+            election.ballot_of(2.into(), Some(&key)).unwrap(),
+            Some(&ballot(5))
+        );
+
+        assert!(election.void(2.into(), Some(&key), Utc::now()).unwrap());
+        assert!(!election.has_voted(2.into()));
+        assert_eq!(election.ballot_count(), 1);
+        assert!(election.ballots.is_empty());
+    }
+
+    #[test]
+    fn test_saved_secret_ballots_dont_name_voters() {
+        let key = BallotKey::new(b"key");
+        let mut election = secret_election();
+        election
+            .cast(123456789.into(), ballot(4), Some(&key), Utc::now())
+            .unwrap();
+
+        let saved = serde_json::to_value(&election).unwrap();
+        assert_eq!(saved["ballots"], serde_json::json!({}));
+        assert_eq!(saved["secret"]["voted"], serde_json::json!(["123456789"]));
+        let ballots = saved["secret"]["ballots"].as_object().unwrap();
+        assert_eq!(ballots.len(), 1);
+        assert!(ballots.keys().all(|token| !token.contains("123456789")));
+
+        // Another key can't find the ballot.
+        let saved: Election = serde_json::from_value(saved).unwrap();
+        let other = BallotKey::new(b"other");
+        assert_eq!(
+            saved.ballot_of(123456789.into(), Some(&other)).unwrap(),
+            None
+        );
+        assert_eq!(
+            saved.ballot_of(123456789.into(), Some(&key)).unwrap(),
+            Some(&ballot(4))
+        );
+    }
+
+    #[test]
+    fn test_secret_ballots_need_a_key() {
+        let mut election = secret_election();
+        assert!(election
+            .cast(2.into(), ballot(1), None, Utc::now())
+            .is_err());
+    }
+
+    #[test]
+    fn test_open_ballots_cant_become_secret() {
+        let mut election = Election::new(1, 1);
+        election.add_candidate("a", "EMEA");
+        election
+            .cast(2.into(), ballot(1), None, Utc::now())
+            .unwrap();
+        assert!(!election.make_secret());
+        assert!(!election.is_secret());
+    }
+}
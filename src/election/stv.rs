@@ -0,0 +1,469 @@
+//! Single Transferable Vote counting.
+//!
+//! Ballots are turned into preference orders by sorting each voter's scores from highest to
+//! lowest. Candidates sharing a score are equally preferred, so a ballot reaching that level is
+//! split evenly between whichever of them are still in the running. Abstentions are unranked.
+//!
+//! Surpluses are transferred fractionally (Gregory method) and only one candidate is elected or
+//! eliminated per round so that every step can be shown to voters.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{Election, Name, Outcome, Region, Seat, Seats, Standing, Status, Tie};
+
+/// Tolerance used when comparing fractional vote totals.
+const EPSILON: f64 = 1e-9;
+
+/// What happened at the end of a round.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// The candidate reached the quota (or could no longer be outnumbered) and took a seat.
+    Elected { name: Name, surplus: f64 },
+    /// The candidate had the fewest votes and their ballots moved on.
+    Eliminated(Name),
+    /// The candidate could not fill any of the remaining offices.
+    Excluded { name: Name, reason: Status },
+}
+
+impl Event {
+    pub fn name(&self) -> &Name {
+        match self {
+            Event::Elected { name, .. }
+            | Event::Eliminated(name)
+            | Event::Excluded { name, .. } => name,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Round {
+    /// Votes held by each continuing candidate when the round was decided.
+    pub tallies: BTreeMap<Name, f64>,
+    /// Ballot weight with no continuing preferences left.
+    pub exhausted: f64,
+    pub event: Event,
+}
+
+#[derive(Debug, Clone)]
+pub struct Count {
+    pub quota: f64,
+    pub rounds: Vec<Round>,
+    /// Ties between the leaders of a round or the candidates with the fewest votes.
+    pub ties: Vec<Tie>,
+    /// The office each elected candidate holds once the count is over.
+    pub seated: BTreeMap<Name, Seat>,
+    /// Offices left empty, by the region they were reserved for.
+    pub vacant: BTreeMap<Region, usize>,
+    /// Whether every office that wasn't left vacant was filled.
+    pub complete: bool,
+}
+
+impl Count {
+    /// Places candidates in the order they were elected, then those still in the running when
+    /// the count ended, then everyone else from the last eliminated to the first.
+    pub(super) fn outcome(self, election: &Election) -> Outcome {
+        let support = election.support();
+        let mut placed = Vec::new();
+        for round in &self.rounds {
+            if let Event::Elected { name, .. } = &round.event {
+                placed.push((
+                    name.clone(),
+                    round.tallies[name],
+                    Status::Elected(self.seated[name].clone()),
+                ));
+            }
+        }
+        if let Some(last) = self.rounds.last() {
+            let mut remaining: Vec<_> = last
+                .tallies
+                .iter()
+                .filter(|(n, _)| self.rounds.iter().all(|r| r.event.name() != *n))
+                .collect();
+            remaining.sort_by(|a, b| b.1.total_cmp(a.1).then_with(|| a.0.cmp(b.0)));
+            for (name, votes) in remaining {
+                placed.push((name.clone(), *votes, Status::NotElected));
+            }
+        }
+        for round in self.rounds.iter().rev() {
+            match &round.event {
+                Event::Elected { .. } => {}
+                Event::Eliminated(name) => {
+                    placed.push((name.clone(), round.tallies[name], Status::NotElected));
+                }
+                Event::Excluded { name, reason } => {
+                    placed.push((name.clone(), round.tallies[name], reason.clone()));
+                }
+            }
+        }
+        for name in election.ineligible() {
+            let status = election.ineligible_status(&name);
+            placed.push((name, 0., status));
+        }
+
+        Outcome {
+            standings: placed
+                .into_iter()
+                .enumerate()
+                .map(|(i, (name, score, status))| Standing {
+                    rank: i + 1,
+                    regions: election.candidates[&name].regions.clone(),
+                    votes: support.get(&name).copied().unwrap_or(0),
+                    score: score as f32,
+                    name,
+                    status,
+                })
+                .collect(),
+            complete: self.complete,
+            vacant: self.vacant,
+            ties: self.ties,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Parcel {
+    ballot: usize,
+    weight: f64,
+}
+
+struct Counter {
+    preferences: Vec<Vec<Vec<Name>>>,
+    hopeful: BTreeSet<Name>,
+    piles: BTreeMap<Name, Vec<Parcel>>,
+    exhausted: f64,
+}
+
+impl Counter {
+    /// Gives a parcel to the highest preference level that still has a continuing candidate.
+    fn allocate(&mut self, parcel: Parcel) {
+        let level = self.preferences[parcel.ballot].iter().find_map(|level| {
+            let continuing: Vec<_> = level.iter().filter(|n| self.hopeful.contains(n)).collect();
+            (!continuing.is_empty()).then_some(continuing)
+        });
+        match level {
+            Some(names) => {
+                let weight = parcel.weight / names.len() as f64;
+                for name in names {
+                    self.piles.entry(name.clone()).or_default().push(Parcel {
+                        ballot: parcel.ballot,
+                        weight,
+                    });
+                }
+            }
+            None => self.exhausted += parcel.weight,
+        }
+    }
+
+    /// Takes a candidate out of the running and hands back their ballots, scaled by `factor`.
+    fn remove(&mut self, name: &Name, factor: f64) {
+        self.hopeful.remove(name);
+        for parcel in self.piles.remove(name).unwrap_or_default() {
+            let weight = parcel.weight * factor;
+            if weight > EPSILON {
+                self.allocate(Parcel { weight, ..parcel });
+            }
+        }
+    }
+
+    fn tallies(&self) -> BTreeMap<Name, f64> {
+        self.hopeful
+            .iter()
+            .map(|n| {
+                let votes = self
+                    .piles
+                    .get(n)
+                    .map(|pile| pile.iter().map(|p| p.weight).sum())
+                    .unwrap_or(0.);
+                (n.clone(), votes)
+            })
+            .collect()
+    }
+}
+
+fn preferences(election: &Election) -> Vec<Vec<Vec<Name>>> {
+    election
+        .cast_ballots()
+        .map(|ballot| {
+            let mut levels = BTreeMap::<usize, Vec<Name>>::new();
+            for (name, rank) in &ballot.votes {
+                if *rank > 0 && election.candidates.contains_key(name) {
+                    levels.entry(*rank).or_default().push(name.clone());
+                }
+            }
+            levels.into_values().rev().collect::<Vec<_>>()
+        })
+        .filter(|levels| !levels.is_empty())
+        .collect()
+}
+
+pub(super) fn count(election: &Election) -> Count {
+    let preferences = preferences(election);
+    let mut seats = Seats::new(election);
+    let quota = (preferences.len() / (seats.remaining() + 1) + 1) as f64;
+    let ineligible = election.ineligible();
+    let mut counter = Counter {
+        hopeful: election
+            .candidates
+            .keys()
+            .filter(|n| !ineligible.contains(n))
+            .cloned()
+            .collect(),
+        preferences,
+        piles: BTreeMap::new(),
+        exhausted: 0.,
+    };
+    for ballot in 0..counter.preferences.len() {
+        counter.allocate(Parcel { ballot, weight: 1. });
+    }
+
+    // Orders the candidates that are level with the first, best placed first.
+    let mut ties = Vec::new();
+    let mut level = |ranked: &[(&Name, &f64)]| -> Vec<Name> {
+        let tied: Vec<Name> = ranked
+            .iter()
+            .take_while(|(_, v)| (**v - *ranked[0].1).abs() < EPSILON)
+            .map(|(n, _)| (*n).clone())
+            .collect();
+        if tied.len() < 2 {
+            return tied;
+        }
+        let (candidates, resolved_by) = election.tie_order(tied);
+        ties.push(Tie {
+            score: *ranked[0].1 as f32,
+            candidates: candidates.clone(),
+            resolved_by,
+        });
+        candidates
+    };
+
+    let mut rounds = Vec::new();
+    while seats.remaining() > 0 && !counter.hopeful.is_empty() {
+        let tallies = counter.tallies();
+
+        let event = if let Some((name, reason)) = counter.hopeful.iter().find_map(|n| {
+            let reason = seats.check(&election.candidates[n].regions).err()?;
+            Some((n.clone(), reason))
+        }) {
+            counter.remove(&name, 1.);
+            Event::Excluded { name, reason }
+        } else {
+            let mut ranked: Vec<_> = tallies.iter().collect();
+            ranked.sort_by(|a, b| b.1.total_cmp(a.1).then_with(|| a.0.cmp(b.0)));
+            let votes = ranked[0].1;
+
+            if *votes + EPSILON >= quota || counter.hopeful.len() <= seats.remaining() {
+                let name = level(&ranked).remove(0);
+                seats
+                    .take(&name, &election.candidates[&name].regions)
+                    .expect("excluded candidates were removed");
+                let surplus = (*votes - quota).max(0.);
+                let factor = if *votes > EPSILON {
+                    surplus / *votes
+                } else {
+                    0.
+                };
+                counter.remove(&name, factor);
+                Event::Elected { name, surplus }
+            } else {
+                // A candidate is protected while one of their regions needs every remaining
+                // hopeful from it to fill its reserved offices.
+                let protected = |n: &Name| {
+                    election.candidates[n].regions.iter().any(|region| {
+                        let hopefuls = counter
+                            .hopeful
+                            .iter()
+                            .filter(|h| election.candidates[*h].regions.contains(region))
+                            .count();
+                        hopefuls <= seats.reserved_for(region)
+                    })
+                };
+                let lowest: Vec<_> = ranked
+                    .iter()
+                    .rev()
+                    .filter(|(n, _)| !protected(n))
+                    .copied()
+                    .collect();
+                let name = level(&lowest)
+                    .pop()
+                    .expect("more hopefuls than remaining offices");
+                counter.remove(&name, 1.);
+                Event::Eliminated(name)
+            }
+        };
+
+        tracing::info!("STV round {}: {event:?}", rounds.len() + 1);
+        rounds.push(Round {
+            tallies,
+            exhausted: counter.exhausted,
+            event,
+        });
+    }
+
+    Count {
+        quota,
+        rounds,
+        ties,
+        seated: seats.seated(),
+        vacant: seats.vacancies(),
+        complete: seats.remaining() == 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::election::{CountingMethod, Region, TieBreak};
+    use test_case::test_case;
+
+    fn election<N: Into<Name>, R: Into<Region>>(
+        offices: usize,
+        reservations: Vec<R>,
+        candidates: Vec<(N, &str)>,
+        ballots: Vec<Vec<(&str, usize)>>,
+    ) -> Election {
+        let mut election = Election::new(1, offices);
+        election.set_method(CountingMethod::Stv);
+        for reserve in reservations {
+            election.reserve_office(reserve);
+        }
+        for (n, r) in candidates {
+            election.add_candidate(n, r);
+        }
+        for (i, ballot) in ballots.into_iter().enumerate() {
+            for (n, rank) in ballot {
+                election.vote((i as u64 + 1).into(), n, rank);
+            }
+        }
+        election.close();
+        election
+    }
+
+    #[test_case(
+        1,
+        vec![],
+        vec![
+            vec![("a", 5), ("b", 4), ("c", 1)],
+            vec![("a", 5), ("b", 4), ("c", 1)],
+            vec![("b", 5), ("a", 1)],
+            vec![("c", 5), ("b", 4)],
+            vec![("c", 5), ("b", 4)],
+        ],
+        vec!["a"];
+        "eliminated votes transfer"
+    )]
+    #[test_case(
+        2,
+        vec![],
+        vec![
+            vec![("a", 5), ("b", 4)],
+            vec![("a", 5), ("b", 4)],
+            vec![("a", 5), ("b", 4)],
+            vec![("a", 5), ("b", 4)],
+            vec![("a", 5), ("b", 4)],
+            vec![("a", 5), ("b", 4)],
+            vec![("a", 5), ("b", 4)],
+            vec![("c", 5)],
+            vec![("c", 5)],
+            vec![("d", 5)],
+        ],
+        vec!["a", "b"];
+        "surplus transfers"
+    )]
+    #[test_case(
+        2,
+        vec!["AMER"],
+        vec![
+            vec![("a", 5), ("b", 4)],
+            vec![("a", 5), ("b", 4)],
+            vec![("a", 5), ("b", 4)],
+            vec![("b", 5)],
+            vec![("b", 5)],
+            vec![("e", 5)],
+        ],
+        vec!["a", "e"];
+        "honours reservations"
+    )]
+    fn test_stv(
+        offices: usize,
+        reservations: Vec<&str>,
+        ballots: Vec<Vec<(&str, usize)>>,
+        expected: Vec<&str>,
+    ) {
+        let election = election(
+            offices,
+            reservations,
+            vec![
+                ("a", "EMEA"),
+                ("b", "EMEA"),
+                ("c", "EMEA"),
+                ("d", "EMEA"),
+                ("e", "AMER"),
+            ],
+            ballots,
+        );
+        let expected: Vec<Name> = expected.into_iter().map(Name::from).collect();
+        assert_eq!(Some(expected), election.run());
+    }
+
+    #[test]
+    fn test_stv_rounds() {
+        let election = election(
+            1,
+            Vec::<&str>::new(),
+            vec![("a", "EMEA"), ("b", "EMEA"), ("c", "EMEA")],
+            vec![
+                vec![("a", 5), ("b", 4)],
+                vec![("a", 5), ("b", 4)],
+                vec![("b", 5)],
+                vec![("c", 5), ("b", 4)],
+                vec![("c", 5), ("b", 4)],
+                vec![("c", 5)],
+            ],
+        );
+        let count = election.stv();
+        assert_eq!(count.quota, 4.);
+        assert_eq!(
+            count
+                .rounds
+                .iter()
+                .map(|r| r.event.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                Event::Eliminated("b".into()),
+                Event::Eliminated("a".into()),
+                Event::Elected {
+                    name: "c".into(),
+                    surplus: 0.
+                },
+            ]
+        );
+        assert_eq!(count.rounds[1].tallies[&Name::from("a")], 2.);
+        assert_eq!(count.rounds[1].tallies[&Name::from("c")], 3.);
+    }
+
+    #[test]
+    fn test_stv_ties() {
+        let mut election = election(
+            1,
+            Vec::<&str>::new(),
+            vec![("a", "EMEA"), ("b", "EMEA"), ("c", "EMEA")],
+            vec![
+                vec![("a", 5)],
+                vec![("a", 5)],
+                vec![("b", 5)],
+                vec![("c", 5)],
+            ],
+        );
+        election.set_tie_break(TieBreak::EarliestNomination);
+        let count = election.stv();
+        assert_eq!(count.rounds[0].event, Event::Eliminated("c".into()));
+        assert_eq!(
+            count.ties,
+            vec![Tie {
+                score: 1.,
+                candidates: vec!["b".into(), "c".into()],
+                resolved_by: vec![TieBreak::EarliestNomination],
+            }]
+        );
+    }
+}
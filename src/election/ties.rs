@@ -0,0 +1,204 @@
+//! Reproducible tie-breaking.
+//!
+//! Every tie is resolved by the election's policy. When a policy can't separate some of the tied
+//! candidates the rest are ordered by a draw: the candidates are sorted by name and shuffled with
+//! ChaCha8 seeded by the election's published seed, so anyone can repeat the draw.
+
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{schulze, Election, Name};
+
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, poise::ChoiceParameter,
+)]
+pub enum TieBreak {
+    /// Shuffle the tied candidates with the election's seed.
+    #[default]
+    #[name = "Seeded draw"]
+    Random,
+
+    /// The candidate nominated first wins.
+    #[name = "Earliest nomination"]
+    EarliestNomination,
+
+    /// Follow the order the owner drew by lot.
+    #[name = "Lot drawn by owner"]
+    OwnerLot,
+
+    /// The candidate preferred by more voters over the other tied candidates wins.
+    #[name = "Head-to-head"]
+    HeadToHead,
+}
+
+/// Candidates who finished with the same score.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tie {
+    pub score: f32,
+    /// The tied candidates, best placed first.
+    pub candidates: Vec<Name>,
+    /// The policies that were needed to separate the candidates, in the order they were applied.
+    pub resolved_by: Vec<TieBreak>,
+}
+
+impl Election {
+    fn draw(&self, mut names: Vec<Name>) -> Vec<Name> {
+        names.sort();
+        let seed = self
+            .tie_seed
+            .expect("the tie-break seed is sealed before results are counted");
+        names.shuffle(&mut ChaCha8Rng::seed_from_u64(seed));
+        names
+    }
+
+    /// Orders tied candidates from best to worst along with the policies that were used.
+    pub(super) fn tie_order(&self, names: Vec<Name>) -> (Vec<Name>, Vec<TieBreak>) {
+        let position = |order: &[Name], n: &Name| order.iter().position(|o| o == n);
+        match self.tie_break {
+            TieBreak::Random => (self.draw(names), vec![TieBreak::Random]),
+            TieBreak::EarliestNomination => {
+                let mut names = names;
+                // Candidates from before nominations were tracked go last, by name.
+                names.sort_by_key(|n| {
+                    (
+                        position(&self.nominated, n).unwrap_or(usize::MAX),
+                        n.clone(),
+                    )
+                });
+                (names, vec![TieBreak::EarliestNomination])
+            }
+            TieBreak::OwnerLot => {
+                if names.iter().all(|n| position(&self.lots, n).is_some()) {
+                    let mut names = names;
+                    names.sort_by_key(|n| position(&self.lots, n));
+                    (names, vec![TieBreak::OwnerLot])
+                } else {
+                    (self.draw(names), vec![TieBreak::Random])
+                }
+            }
+            TieBreak::HeadToHead => {
+                let refs: Vec<&Name> = names.iter().collect();
+                let d = schulze::pairwise(self, &refs);
+                let wins: Vec<usize> = (0..names.len())
+                    .map(|i| (0..names.len()).filter(|&j| d[i][j] > d[j][i]).count())
+                    .collect();
+
+                // Anyone still level on head-to-head wins is ordered by the seeded draw.
+                let drawn = self.draw(names.clone());
+                let mut order: Vec<usize> = (0..names.len()).collect();
+                order.sort_by_key(|&i| (std::cmp::Reverse(wins[i]), position(&drawn, &names[i])));
+                let mut resolved_by = vec![TieBreak::HeadToHead];
+                if order.windows(2).any(|w| wins[w[0]] == wins[w[1]]) {
+                    resolved_by.push(TieBreak::Random);
+                }
+                (
+                    order.into_iter().map(|i| names[i].clone()).collect(),
+                    resolved_by,
+                )
+            }
+        }
+    }
+
+    /// Sorts scores from lowest to highest, breaking ties by the election's policy.
+    pub(super) fn break_ties(&self, mut results: Vec<(f32, Name)>) -> (Vec<(f32, Name)>, Vec<Tie>) {
+        results.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+
+        let mut sorted = Vec::with_capacity(results.len());
+        let mut ties = Vec::new();
+        for group in results.chunk_by(|a, b| a.0 == b.0) {
+            let score = group[0].0;
+            if group.len() == 1 {
+                sorted.push(group[0].clone());
+                continue;
+            }
+            let (candidates, resolved_by) =
+                self.tie_order(group.iter().map(|(_, n)| n.clone()).collect());
+            sorted.extend(candidates.iter().rev().map(|n| (score, n.clone())));
+            ties.push(Tie {
+                score,
+                candidates,
+                resolved_by,
+            });
+        }
+        // Report the ties closest to the top first.
+        ties.reverse();
+
+        (sorted, ties)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_case::test_case;
+
+    fn tied_election(tie_break: TieBreak) -> Election {
+        let mut election = Election::new(1, 1);
+        election.set_tie_break(tie_break);
+        for n in ["c", "a", "d", "b"] {
+            election.add_candidate(n, "EMEA");
+        }
+        election.seal_seed();
+        election
+    }
+
+    fn names(names: &[&str]) -> Vec<Name> {
+        names.iter().map(|n| Name::from(*n)).collect()
+    }
+
+    #[test]
+    fn test_seeded_draw_is_reproducible() {
+        let election = tied_election(TieBreak::Random);
+        let first = election.tie_order(names(&["a", "b", "c", "d"]));
+        let second = election.tie_order(names(&["d", "c", "b", "a"]));
+        assert_eq!(first, second);
+        assert_eq!(first.1, vec![TieBreak::Random]);
+    }
+
+    #[test_case(TieBreak::EarliestNomination, vec![], vec!["c", "a", "d", "b"], vec![TieBreak::EarliestNomination]; "nomination")]
+    #[test_case(TieBreak::OwnerLot, vec!["b", "d", "a", "c"], vec!["b", "d", "a", "c"], vec![TieBreak::OwnerLot]; "owner lot")]
+    fn test_tie_order(
+        tie_break: TieBreak,
+        lots: Vec<&str>,
+        expected: Vec<&str>,
+        resolved_by: Vec<TieBreak>,
+    ) {
+        let mut election = tied_election(tie_break);
+        election.set_lots(names(&lots));
+        assert_eq!(
+            election.tie_order(names(&["a", "b", "c", "d"])),
+            (names(&expected), resolved_by)
+        );
+    }
+
+    #[test]
+    fn test_missing_lot_falls_back_to_draw() {
+        let mut election = tied_election(TieBreak::OwnerLot);
+        election.set_lots(names(&["b", "a"]));
+        let (_, resolved_by) = election.tie_order(names(&["a", "b", "c"]));
+        assert_eq!(resolved_by, vec![TieBreak::Random]);
+    }
+
+    #[test]
+    fn test_head_to_head() {
+        let mut election = tied_election(TieBreak::HeadToHead);
+        election.vote(1.into(), "a", 5);
+        election.vote(1.into(), "b", 2);
+        election.vote(2.into(), "a", 1);
+        election.vote(2.into(), "b", 5);
+        election.vote(3.into(), "a", 4);
+        election.vote(3.into(), "b", 3);
+
+        let (results, ties) = election.break_ties(election.tally());
+        assert_eq!(results.last().map(|(_, n)| n), Some(&"a".into()));
+        assert_eq!(
+            ties,
+            vec![Tie {
+                score: 10. / 3.,
+                candidates: names(&["a", "b"]),
+                resolved_by: vec![TieBreak::HeadToHead],
+            }]
+        );
+    }
+}
@@ -1,6 +1,6 @@
 #![deny(unused)]
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
 use actions::{Action, ElectionAction, ElectionActionType, VoteAction, VoteActionType};
 use anyhow::{anyhow, Context as _};
@@ -16,7 +16,7 @@ use poise::{
     CreateReply, FrameworkContext,
 };
 use serde::{Deserialize, Serialize};
-use tokio::sync::{RwLockReadGuard, RwLockWriteGuard};
+use tokio::sync::RwLockWriteGuard;
 use tracing::warn;
 use tracing_subscriber::{layer::SubscriberExt as _, Layer as _, Registry};
 
@@ -38,6 +38,10 @@ struct VoteInProgress {
 
     #[serde(default)]
     expires_at: DateTime<Utc>,
+
+    /// Whether the vote is on a secret election, and so must not be saved.
+    #[serde(skip)]
+    secret: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -94,14 +98,34 @@ struct ElectionMap {
 struct VoteMap {
     #[serde(default)]
     next_vote_id: actions::VoteId,
-    #[serde(deserialize_with = "id_map")]
+    #[serde(deserialize_with = "id_map", serialize_with = "open_votes")]
     votes: HashMap<actions::VoteId, VoteInProgress>,
 }
 
+/// Votes in progress on secret elections stay in memory, so their partial ballots never reach
+/// disk.
+fn open_votes<S: serde::Serializer>(
+    votes: &HashMap<actions::VoteId, VoteInProgress>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(votes.iter().filter(|(_, vote)| !vote.secret))
+}
+
+/// The key secret ballots are stored under, read from the environment at startup.
+static BALLOT_KEY: std::sync::OnceLock<Option<election::BallotKey>> = std::sync::OnceLock::new();
+
+fn ballot_key() -> Option<&'static election::BallotKey> {
+    BALLOT_KEY.get().and_then(Option::as_ref)
+}
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 struct V2Elections {
     elections: ElectionMap,
     votes: VoteMap,
+
+    /// Members who don't want reminder DMs.
+    #[serde(default)]
+    reminder_opt_outs: BTreeSet<serenity::UserId>,
 }
 
 impl Elections {
@@ -124,6 +148,7 @@ impl Elections {
         }
     }
 
+    #[allow(unused)]
     fn try_latest(&self) -> Option<&V2Elections> {
         match self {
             Elections::V2(v2_elections) => Some(v2_elections),
@@ -137,6 +162,7 @@ impl VoteMap {
         &mut self,
         election: EID,
         interaction: &serenity::ComponentInteraction,
+        secret: bool,
     ) -> actions::VoteId {
         let vote_id = self.next_vote_id.next();
         self.votes.insert(
@@ -149,6 +175,7 @@ impl VoteMap {
 
                 expires_at: Utc::now() + TimeDelta::hours(1),
                 partial_ballot: election::Ballot::default(),
+                secret,
             },
         );
         vote_id
@@ -158,22 +185,29 @@ impl VoteMap {
         self.votes.remove(&vote.into())
     }
 
+    /// Casts the finished ballot, returning the voter's receipt if receipts can be issued.
     fn save_ballot<VID: Into<actions::VoteId>>(
         &mut self,
         vote: VID,
         elections: &mut ElectionMap,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<Option<String>, anyhow::Error> {
         let vote = vote.into();
         let election = elections.get_mut(vote, self)?;
+        if election.state() != election::State::Open {
+            return Err(anyhow!("Voting closed before the ballot was finished"));
+        }
         let vote = self
             .votes
             .get_mut(&vote)
             .ok_or_else(|| anyhow!("Could not get vote in progress"))?;
         let mut ballot = election::Ballot::default();
         std::mem::swap(&mut ballot, &mut vote.partial_ballot);
-        election.ballots.insert(vote.user, ballot);
+        election.cast(vote.user, ballot, ballot_key(), Utc::now())?;
 
-        Ok(())
+        match ballot_key() {
+            Some(key) => election.receipt(vote.election.into(), vote.user, key),
+            None => Ok(None),
+        }
     }
 
     fn get<VID: Into<actions::VoteId>>(&self, vote: VID) -> Result<&VoteInProgress, anyhow::Error> {
@@ -266,8 +300,8 @@ impl V2Elections {
         action: actions::VoteAction,
         interaction: &serenity::ComponentInteraction,
     ) -> Result<(), anyhow::Error> {
-        let edit = serenity::EditMessage::new()
-            .embed(self.elections.get(action, &self.votes)?.make_embed());
+        let election_id = self.votes.get(action)?.election;
+        let edit = election_edit(election_id, self.elections.get(action, &self.votes)?);
         serenity::Builder::execute(
             edit,
             ctx,
@@ -283,18 +317,245 @@ impl V2Elections {
 }
 
 impl data::Migrate for Elections {
-    fn migrate(&mut self) {}
+    fn migrate(&mut self) {
+        let elections = match self {
+            Elections::V1(v1) => &mut v1.elections,
+            Elections::V2(v2) => &mut v2.elections.elections,
+        };
+        for (id, election) in elections.iter_mut() {
+            election.migrate();
+            if let Err(problem) = election.verify_log() {
+                warn!("Election {id} failed verification: {problem}");
+            }
+        }
+    }
+}
+
+/// Takes the JSON object at `pointer` out of `value`, leaving an empty one.
+fn take_object(
+    value: &mut serde_json::Value,
+    pointer: &str,
+) -> serde_json::Map<String, serde_json::Value> {
+    match value.pointer_mut(pointer) {
+        Some(object) => match object.take() {
+            serde_json::Value::Object(map) => {
+                *object = serde_json::Value::Object(serde_json::Map::new());
+                map
+            }
+            other => {
+                *object = other;
+                serde_json::Map::new()
+            }
+        },
+        None => serde_json::Map::new(),
+    }
+}
+
+/// Elections, their ballots and votes in progress each get a row, keyed by ID. Ballots are keyed
+/// by election and voter, or by election and token in secret elections.
+impl data::Rows for Elections {
+    fn to_rows(&self) -> Result<Vec<data::Row>, anyhow::Error> {
+        use data::{Row, Table};
+
+        let mut guild = serde_json::to_value(self)?;
+        let mut rows = Vec::new();
+        if let Elections::V2(_) = self {
+            for (id, mut election) in take_object(&mut guild, "/elections/elections") {
+                for (voter, ballot) in take_object(&mut election, "/ballots") {
+                    rows.push(Row {
+                        table: Table::Ballots,
+                        key: format!("{id}/{voter}"),
+                        value: ballot,
+                    });
+                }
+                for (token, ballot) in take_object(&mut election, "/secret/ballots") {
+                    rows.push(Row {
+                        table: Table::Ballots,
+                        key: format!("{id}/secret/{token}"),
+                        value: ballot,
+                    });
+                }
+                rows.push(Row {
+                    table: Table::Elections,
+                    key: id,
+                    value: election,
+                });
+            }
+            for (id, vote) in take_object(&mut guild, "/votes/votes") {
+                rows.push(Row {
+                    table: Table::Votes,
+                    key: id,
+                    value: vote,
+                });
+            }
+        }
+        rows.push(Row {
+            table: Table::Guilds,
+            key: String::new(),
+            value: guild,
+        });
+        Ok(rows)
+    }
+
+    fn from_rows(rows: Vec<data::Row>) -> Result<Self, anyhow::Error> {
+        use data::Table;
+
+        let mut guild = None;
+        let mut elections = serde_json::Map::new();
+        let mut votes = serde_json::Map::new();
+        let mut ballots = Vec::new();
+        for row in rows {
+            match row.table {
+                Table::Guilds => guild = Some(row.value),
+                Table::Elections => {
+                    elections.insert(row.key, row.value);
+                }
+                Table::Ballots => ballots.push((row.key, row.value)),
+                Table::Votes => {
+                    votes.insert(row.key, row.value);
+                }
+            }
+        }
+        let mut guild = guild.ok_or_else(|| anyhow!("The guild's row is missing"))?;
+
+        for (key, ballot) in ballots {
+            let (id, voter) = key
+                .split_once('/')
+                .ok_or_else(|| anyhow!("Ballot {key} has no election"))?;
+            let election = elections
+                .get_mut(id)
+                .ok_or_else(|| anyhow!("Ballot {key} is for a missing election"))?;
+            let pointer = match voter.strip_prefix("secret/") {
+                Some(_) => "/secret/ballots",
+                None => "/ballots",
+            };
+            let voter = voter.strip_prefix("secret/").unwrap_or(voter);
+            election
+                .pointer_mut(pointer)
+                .and_then(serde_json::Value::as_object_mut)
+                .ok_or_else(|| anyhow!("Election {id} can't hold ballot {key}"))?
+                .insert(voter.to_owned(), ballot);
+        }
+        if let Some(object) = guild.pointer_mut("/elections/elections") {
+            *object = serde_json::Value::Object(elections);
+        }
+        if let Some(object) = guild.pointer_mut("/votes/votes") {
+            *object = serde_json::Value::Object(votes);
+        }
+        Ok(serde_json::from_value(guild)?)
+    }
 }
 
 type Context<'a> = poise::Context<'a, data::GlobalState<Elections>, anyhow::Error>;
+type ApplicationContext<'a> =
+    poise::ApplicationContext<'a, data::GlobalState<Elections>, anyhow::Error>;
 
+/// Manage TEA House elections
+#[poise::command(
+    slash_command,
+    guild_only = true,
+    subcommands(
+        "create",
+        "add_candidate",
+        "describe_candidate",
+        "remove_candidate",
+        "withdraw_candidate",
+        "set_offices",
+        "set_quota",
+        "set_winner_role",
+        "set_eligibility",
+        "set_roll",
+        "non_voters",
+        "set_reminders",
+        "reminders",
+        "close",
+        "reopen",
+        "delete"
+    ),
+    subcommand_required
+)]
+async fn election(_ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    Ok(())
+}
+
+#[derive(Debug, poise::Modal)]
+#[name = "Candidates"]
+struct CandidatesModal {
+    #[name = "One per line: name; regions"]
+    #[placeholder = "Ann; AMER\nBo; AMER/EMEA\nCy"]
+    #[paragraph]
+    candidates: String,
+}
+
+/// Post a new election
+#[allow(clippy::too_many_arguments)]
 #[poise::command(slash_command, guild_only = true)]
-async fn election(
-    ctx: Context<'_>,
+async fn create(
+    app_ctx: ApplicationContext<'_>,
     offices: usize,
-    reserved_offices: String,
-    candidates: String,
+    #[description = "Comma separated regions, e.g. AMER, EMEA:2, APAC:0-1 (min-max offices)"]
+    quotas: Option<String>,
+    #[description = "What happens to reserved offices a region can't fill"] vacancies: Option<
+        election::VacancyPolicy,
+    >,
+    #[description = "Comma separated name;region, e.g. Ann;AMER, Bo;AMER/EMEA, Cy"]
+    candidates: Option<String>,
+    #[description = "CSV (name,regions) or JSON list of candidates, instead of typing them"]
+    candidates_file: Option<serenity::Attachment>,
+    method: Option<election::CountingMethod>,
+    kind: Option<election::ElectionKind>,
+    #[description = "Score range such as 0-10 (default 1-5)"] scale: Option<String>,
+    #[description = "Votes (e.g. 5) or share of ballots (e.g. 10%) needed to be elected"]
+    min_support: Option<String>,
+    #[description = "Shrink average scores toward the election mean"] bayesian: Option<bool>,
+    tie_break: Option<election::TieBreak>,
+    #[description = "Comma separated tie-break order drawn by lot, best placed first"] lots: Option<
+        String,
+    >,
+    #[description = "When voting opens, e.g. 2024-06-01 18:00 (UTC)"] opens_at: Option<String>,
+    #[description = "When voting closes, e.g. 2024-06-08 18:00 (UTC)"] closes_at: Option<String>,
+    #[description = "Hours before closing to remind the voter roll, e.g. 24, 1"] reminders: Option<
+        String,
+    >,
+    #[description = "Store ballots so they can't be linked to voters (default false)"]
+    secret: Option<bool>,
+    #[description = "Channel to post the winners in when voting closes"]
+    #[channel_types("Text")]
+    announce_in: Option<serenity::GuildChannel>,
+    #[description = "Keep candidates off their own ballot (default false)"]
+    forbid_self_votes: Option<bool>,
+    #[description = "Role given to winners when the results are certified"] winner_role: Option<
+        serenity::Role,
+    >,
+    #[description = "Take the winner role from members who weren't elected (default false)"]
+    replace_role_holders: Option<bool>,
+    #[description = "Only members with this role can vote"] voter_role: Option<serenity::Role>,
+    #[description = "Members with this role can't vote"] excluded_role: Option<serenity::Role>,
+    #[description = "Days voters must have been in the server"] min_membership_days: Option<u32>,
+    #[description = "Days old voters' accounts must be"] min_account_days: Option<u32>,
 ) -> Result<(), anyhow::Error> {
+    let ctx = Context::Application(app_ctx);
+    // With neither option given, ask for the candidates one per line.
+    let nominations = match (candidates, candidates_file) {
+        (Some(_), Some(_)) => {
+            return Err(anyhow!("Give candidates or candidates_file, not both"));
+        }
+        (Some(candidates), None) => election::parse_list(&candidates)?,
+        (None, Some(file)) => election::parse_file(&file.filename, &file.download().await?)?,
+        (None, None) => {
+            let Some(modal) = poise::execute_modal::<_, _, CandidatesModal>(
+                app_ctx,
+                None,
+                Some(std::time::Duration::from_secs(600)),
+            )
+            .await?
+            else {
+                return Ok(());
+            };
+            election::parse_lines(&modal.candidates)?
+        }
+    };
+
     let guild_id = ctx
         .guild_id()
         .ok_or_else(|| anyhow::anyhow!("No guild id. Must be in a guild"))?;
@@ -303,43 +564,796 @@ async fn election(
     let guild = guild.latest();
 
     let mut election = election::Election::new(ctx.author(), offices);
-    for office in reserved_offices.split(',') {
-        if !election.reserve_office(office.trim()) {
-            return Err(anyhow!("Too many office reservations"));
+    election.set_method(method.unwrap_or_default());
+    election.set_kind(kind.unwrap_or_default());
+    if let Some(scale) = scale {
+        election.set_scale(scale.parse()?);
+    }
+    if let Some(min_support) = min_support {
+        election.set_min_support(Some(min_support.parse()?));
+    }
+    election.set_bayesian(bayesian.unwrap_or_default());
+    for quota in quotas.iter().flat_map(|q| q.split(',')) {
+        let (region, quota) = election::parse_quota(quota)?;
+        election.set_quota(region, quota)?;
+    }
+    election.set_vacancy_policy(vacancies.unwrap_or_default());
+    for nomination in nominations {
+        election.add_candidate(nomination.name, nomination.regions);
+    }
+    election.set_tie_break(tie_break.unwrap_or_default());
+    if let Some(lots) = lots {
+        let lots = lots.split(',').map(|n| n.trim().into()).collect();
+        if !election.set_lots(lots) {
+            return Err(anyhow!("Lots can only name candidates"));
         }
     }
-    for candidate in candidates.split(',') {
-        let (candidate, region) = candidate
-            .split_once(";")
-            .ok_or_else(|| anyhow!("Could not split candidate {candidate} at ;"))?;
-        election.add_candidate(candidate.trim(), region.trim());
+    election.set_schedule(
+        opens_at.as_deref().map(election::parse_time).transpose()?,
+        closes_at.as_deref().map(election::parse_time).transpose()?,
+        Utc::now(),
+    )?;
+    election.set_reminders(
+        election::parse_reminders(reminders.as_deref().unwrap_or_default())?,
+        Utc::now(),
+    );
+    election.set_announce_in(announce_in.map(|c| c.id));
+    if secret.unwrap_or_default() {
+        if ballot_key().is_none() {
+            return Err(anyhow!(
+                "Secret ballots need {} to be configured",
+                election::BallotKey::VAR
+            ));
+        }
+        election.make_secret();
+    }
+    election.set_forbid_self_votes(forbid_self_votes.unwrap_or_default());
+    election.set_winner_role(winner_role.map(|r| r.id));
+    election.set_replace_role_holders(replace_role_holders.unwrap_or_default());
+    let mut eligibility = election::Eligibility::default();
+    if let Some(role) = voter_role {
+        eligibility.require_role(role.id);
     }
+    if let Some(role) = excluded_role {
+        eligibility.exclude_role(role.id);
+    }
+    eligibility.set_min_membership_days(min_membership_days);
+    eligibility.set_min_account_days(min_account_days);
+    election.set_eligibility(eligibility);
 
     let election_id = guild.elections.next_election_id.next();
 
     let reply = CreateReply::default()
         .embed(election.make_embed())
-        .components(vec![CreateActionRow::Buttons(vec![
+        .components(election_components(election_id, election.state()));
+    let handle = ctx.send(reply).await?;
+    let message = handle.message().await?;
+    election.set_message(message.channel_id, message.id);
+    guild.elections.elections.insert(election_id, election);
+    data.persist()?;
+
+    Ok(())
+}
+
+async fn autocomplete_election(
+    ctx: Context<'_>,
+    partial: &str,
+) -> Vec<serenity::AutocompleteChoice> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Vec::new();
+    };
+    let mut data = ctx.data().write().await;
+    let guild = data.guild_mut(guild_id).latest();
+    let mut elections: Vec<_> = guild
+        .elections
+        .elections
+        .iter()
+        .filter(|(id, e)| *e.owner() == ctx.author().id && id.to_string().starts_with(partial))
+        .collect();
+    // Newest first.
+    elections.sort_by_key(|(id, _)| std::cmp::Reverse(usize::from(**id)));
+    elections
+        .into_iter()
+        .map(|(id, e)| {
+            serenity::AutocompleteChoice::new(
+                format!(
+                    "Election {id} ({}, {} candidates)",
+                    e.state(),
+                    e.candidates.len()
+                ),
+                usize::from(*id),
+            )
+        })
+        .collect()
+}
+
+/// Applies a change to one of the author's elections, then refreshes the election message and
+/// publishes the results if the change closed voting.
+async fn edit_election(
+    ctx: Context<'_>,
+    election_id: usize,
+    change: impl FnOnce(&mut election::Election) -> Result<String, anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow::anyhow!("No guild id. Must be in a guild"))?;
+    let election_id = actions::ElectionId::from(election_id);
+    let mut data = ctx.data().write().await;
+    let guild = data.guild_mut(guild_id).latest();
+    let election = guild
+        .elections
+        .elections
+        .get_mut(&election_id)
+        .ok_or_else(|| anyhow!("There is no election {election_id}"))?;
+    if *election.owner() != ctx.author().id {
+        return Err(anyhow!("Only the creator of an election can change it"));
+    }
+
+    let before = election.state();
+    let reply = change(election)?;
+    let closed = before != election::State::Closed && election.state() == election::State::Closed;
+    data.persist()?;
+
+    let guild = data.guild_mut(guild_id).latest();
+    let election = guild
+        .elections
+        .elections
+        .get_mut(&election_id)
+        .expect("election was just changed");
+    let update = if closed {
+        publish_results(ctx.serenity_context(), election_id, election).await
+    } else {
+        refresh_election(ctx.serenity_context(), election_id, election).await
+    };
+    if let Err(e) = update {
+        warn!("Could not update election {election_id}'s message: {e:?}");
+    }
+    if closed {
+        // Publishing records whether the results were announced.
+        data.persist()?;
+    }
+
+    ctx.send(CreateReply::default().ephemeral(true).content(reply))
+        .await?;
+    Ok(())
+}
+
+/// Add a candidate to an election
+#[poise::command(slash_command, guild_only = true, rename = "add-candidate")]
+async fn add_candidate(
+    ctx: Context<'_>,
+    #[description = "Election to change"]
+    #[autocomplete = "autocomplete_election"]
+    election: usize,
+    #[description = "Defaults to the member's display name"] name: Option<String>,
+    #[description = "Regions separated by /, e.g. AMER/EMEA"] regions: Option<String>,
+    #[description = "The member standing as this candidate"] user: Option<serenity::User>,
+) -> Result<(), anyhow::Error> {
+    edit_election(ctx, election, |election| {
+        let name = match (&name, &user) {
+            (Some(name), _) => name.trim().to_string(),
+            (None, Some(user)) => user.display_name().to_string(),
+            (None, None) => String::new(),
+        };
+        if name.is_empty() {
+            return Err(anyhow!("Candidates need a name"));
+        }
+        if !election.add_candidate(name.as_str(), regions.unwrap_or_default()) {
+            return Err(anyhow!("{name} is already a candidate"));
+        }
+        if let Some(user) = user {
+            if let Err(e) = election.set_profile(&name.as_str().into(), None, None, Some(user.id)) {
+                election.remove_candidate(&name.as_str().into(), Utc::now());
+                return Err(e);
+            }
+        }
+        Ok(format!("Added {name}"))
+    })
+    .await
+}
+
+/// Remove a candidate and every vote cast for them
+#[poise::command(slash_command, guild_only = true, rename = "remove-candidate")]
+async fn remove_candidate(
+    ctx: Context<'_>,
+    #[description = "Election to change"]
+    #[autocomplete = "autocomplete_election"]
+    election: usize,
+    name: String,
+) -> Result<(), anyhow::Error> {
+    edit_election(ctx, election, |election| {
+        let name = name.trim();
+        if !election.remove_candidate(&name.into(), Utc::now()) {
+            return Err(anyhow!("{name} is not a candidate"));
+        }
+        Ok(format!("Removed {name}"))
+    })
+    .await
+}
+
+/// Set the statement, link and Discord account shown to voters for a candidate
+#[poise::command(slash_command, guild_only = true, rename = "describe-candidate")]
+async fn describe_candidate(
+    ctx: Context<'_>,
+    #[description = "Election to change"]
+    #[autocomplete = "autocomplete_election"]
+    election: usize,
+    name: String,
+    #[description = "The candidate's statement to voters"] statement: Option<String>,
+    #[description = "A web page about the candidate"] link: Option<String>,
+    #[description = "The candidate's Discord account, if it should change"] user: Option<
+        serenity::User,
+    >,
+) -> Result<(), anyhow::Error> {
+    edit_election(ctx, election, |election| {
+        let name = election::Name::from(name.trim());
+        let user = match user {
+            Some(user) => Some(user.id),
+            None => election.candidates.get(&name).and_then(|c| c.user()),
+        };
+        election.set_profile(&name, statement, link, user)?;
+        Ok(format!("Updated {name}'s profile"))
+    })
+    .await
+}
+
+/// Withdraw a candidate, keeping the votes already cast
+#[poise::command(slash_command, guild_only = true, rename = "withdraw-candidate")]
+async fn withdraw_candidate(
+    ctx: Context<'_>,
+    #[description = "Election to change"]
+    #[autocomplete = "autocomplete_election"]
+    election: usize,
+    name: String,
+) -> Result<(), anyhow::Error> {
+    edit_election(ctx, election, |election| {
+        let name = name.trim();
+        if !election.withdraw_candidate(&name.into()) {
+            return Err(anyhow!(
+                "{name} is not a candidate or has already withdrawn"
+            ));
+        }
+        Ok(format!("{name} has withdrawn"))
+    })
+    .await
+}
+
+/// Change how many offices are being filled
+#[poise::command(slash_command, guild_only = true, rename = "set-offices")]
+async fn set_offices(
+    ctx: Context<'_>,
+    #[description = "Election to change"]
+    #[autocomplete = "autocomplete_election"]
+    election: usize,
+    offices: usize,
+) -> Result<(), anyhow::Error> {
+    edit_election(ctx, election, |election| {
+        election.set_offices(offices)?;
+        Ok(format!("The election now fills {offices} offices"))
+    })
+    .await
+}
+
+/// Change the role given to winners when the results are certified
+#[poise::command(slash_command, guild_only = true, rename = "set-winner-role")]
+async fn set_winner_role(
+    ctx: Context<'_>,
+    #[description = "Election to change"]
+    #[autocomplete = "autocomplete_election"]
+    election: usize,
+    #[description = "Leave out to stop giving winners a role"] role: Option<serenity::Role>,
+    #[description = "Take the role from members who weren't elected (leave out to keep as is)"]
+    replace_role_holders: Option<bool>,
+) -> Result<(), anyhow::Error> {
+    edit_election(ctx, election, |election| {
+        election.set_winner_role(role.as_ref().map(|r| r.id));
+        if let Some(replace) = replace_role_holders {
+            election.set_replace_role_holders(replace);
+        }
+        Ok(match role {
+            Some(role) => format!("Winners will be given {}", role.name),
+            None => "Winners won't be given a role".into(),
+        })
+    })
+    .await
+}
+
+/// Change who can vote. Options left out keep their current setting.
+#[poise::command(slash_command, guild_only = true, rename = "set-eligibility")]
+async fn set_eligibility(
+    ctx: Context<'_>,
+    #[description = "Election to change"]
+    #[autocomplete = "autocomplete_election"]
+    election: usize,
+    #[description = "Let members with this role vote; voters need one such role"]
+    require_role: Option<serenity::Role>,
+    #[description = "Stop members with this role voting"] exclude_role: Option<serenity::Role>,
+    #[description = "Stop requiring or excluding this role"] clear_role: Option<serenity::Role>,
+    #[description = "Days voters must have been in the server, 0 for none"]
+    min_membership_days: Option<u32>,
+    #[description = "Days old voters' accounts must be, 0 for none"] min_account_days: Option<u32>,
+) -> Result<(), anyhow::Error> {
+    edit_election(ctx, election, |election| {
+        let mut eligibility = election.eligibility().clone();
+        if let Some(role) = require_role {
+            eligibility.require_role(role.id);
+        }
+        if let Some(role) = exclude_role {
+            eligibility.exclude_role(role.id);
+        }
+        if let Some(role) = clear_role {
+            eligibility.clear_role(role.id);
+        }
+        if min_membership_days.is_some() {
+            eligibility.set_min_membership_days(min_membership_days);
+        }
+        if min_account_days.is_some() {
+            eligibility.set_min_account_days(min_account_days);
+        }
+        let reply = match eligibility.describe() {
+            Some(rules) => format!("Voters must meet these rules:\n{rules}"),
+            None => "Everyone can vote".into(),
+        };
+        election.set_eligibility(eligibility);
+        Ok(reply)
+    })
+    .await
+}
+
+/// Limit voting to a fixed electorate, uploaded or taken from a role when voting opens
+#[poise::command(slash_command, guild_only = true, rename = "set-roll")]
+async fn set_roll(
+    ctx: Context<'_>,
+    #[description = "Election to change"]
+    #[autocomplete = "autocomplete_election"]
+    election: usize,
+    #[description = "User IDs or mentions separated by commas or new lines"] file: Option<
+        serenity::Attachment,
+    >,
+    #[description = "Snapshot this role's members when voting opens"] role: Option<serenity::Role>,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow::anyhow!("No guild id. Must be in a guild"))?;
+    let roll = match &file {
+        Some(file) => Some(election::parse_roll(
+            std::str::from_utf8(&file.download().await?)
+                .map_err(|_| anyhow!("{} is not a text file", file.filename))?,
+        )?),
+        None => None,
+    };
+    // Elections that are already open take the snapshot straight away.
+    let members = match &role {
+        Some(role) => Some(role_holders(ctx.serenity_context(), guild_id, role.id).await?),
+        None => None,
+    };
+    edit_election(ctx, election, |election| match (roll, role, members) {
+        (Some(_), Some(_), _) => Err(anyhow!("Give a file or a role, not both")),
+        (Some(roll), None, _) => {
+            let reply = format!("The voter roll has {} members", roll.len());
+            election.set_roll(Some(roll));
+            Ok(reply)
+        }
+        (None, Some(role), members) => {
+            election.set_roll_role(role.id);
+            match members.filter(|_| election.pending_snapshot().is_some()) {
+                Some(members) => {
+                    election.snapshot_roll(members.into_iter().collect());
+                    Ok(format!(
+                        "The voter roll is the {} members of {}",
+                        election.roll().map_or(0, |r| r.len()),
+                        role.name
+                    ))
+                }
+                None => Ok(format!(
+                    "The voter roll will be {}'s members when voting opens",
+                    role.name
+                )),
+            }
+        }
+        (None, None, _) => {
+            election.set_roll(None);
+            Ok("Anyone who meets the eligibility rules can vote".into())
+        }
+    })
+    .await
+}
+
+/// List the members on the voter roll who haven't voted yet
+#[poise::command(slash_command, guild_only = true, rename = "non-voters")]
+async fn non_voters(
+    ctx: Context<'_>,
+    #[description = "Election to check"]
+    #[autocomplete = "autocomplete_election"]
+    election: usize,
+) -> Result<(), anyhow::Error> {
+    // Discord embed descriptions hold 4096 characters, about 100 mentions.
+    const SHOWN: usize = 100;
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow::anyhow!("No guild id. Must be in a guild"))?;
+    let election_id = actions::ElectionId::from(election);
+    let mut data = ctx.data().write().await;
+    let guild = data.guild_mut(guild_id).latest();
+    let election = guild
+        .elections
+        .elections
+        .get(&election_id)
+        .ok_or_else(|| anyhow!("There is no election {election_id}"))?;
+    if *election.owner() != ctx.author().id {
+        return Err(anyhow!(
+            "Only the creator of an election can see who hasn't voted"
+        ));
+    }
+    let non_voters = election
+        .non_voters()
+        .ok_or_else(|| anyhow!("Election {election_id} has no voter roll"))?;
+    let mut lines: Vec<_> = non_voters
+        .iter()
+        .take(SHOWN)
+        .map(|u| format!("* <@{u}>"))
+        .collect();
+    if non_voters.len() > SHOWN {
+        lines.push(format!("…and {} more", non_voters.len() - SHOWN));
+    }
+    let embed = serenity::CreateEmbed::new()
+        .title(format!("Haven't voted ({})", election.turnout()))
+        .color(serenity::Color::BLURPLE)
+        .description(if lines.is_empty() {
+            "Everyone on the roll has voted.".into()
+        } else {
+            lines.join("\n")
+        });
+    ctx.send(CreateReply::default().ephemeral(true).embed(embed))
+        .await?;
+    Ok(())
+}
+
+/// Change when members of the voter roll who haven't voted are reminded
+#[poise::command(slash_command, guild_only = true, rename = "set-reminders")]
+async fn set_reminders(
+    ctx: Context<'_>,
+    #[description = "Election to change"]
+    #[autocomplete = "autocomplete_election"]
+    election: usize,
+    #[description = "Hours before closing, e.g. 24, 1. Leave out for no reminders"] hours: Option<
+        String,
+    >,
+) -> Result<(), anyhow::Error> {
+    let hours = election::parse_reminders(hours.as_deref().unwrap_or_default())?;
+    edit_election(ctx, election, |election| {
+        election.set_reminders(hours, Utc::now());
+        let hours: Vec<_> = election
+            .reminders()
+            .iter()
+            .rev()
+            .map(|h| format!("{h}h"))
+            .collect();
+        Ok(match (hours.is_empty(), election.roll().is_some()) {
+            (true, _) => "No reminders will be sent".into(),
+            (false, true) => format!(
+                "Non-voters will be reminded {} before closing",
+                hours.join(", ")
+            ),
+            (false, false) => format!(
+                "Non-voters will be reminded {} before closing, once the election has a voter roll",
+                hours.join(", ")
+            ),
+        })
+    })
+    .await
+}
+
+/// Turn reminder DMs about elections you haven't voted in on or off
+#[poise::command(slash_command, guild_only = true)]
+async fn reminders(ctx: Context<'_>, enabled: bool) -> Result<(), anyhow::Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow::anyhow!("No guild id. Must be in a guild"))?;
+    let mut data = ctx.data().write().await;
+    let guild = data.guild_mut(guild_id).latest();
+    if enabled {
+        guild.reminder_opt_outs.remove(&ctx.author().id);
+    } else {
+        guild.reminder_opt_outs.insert(ctx.author().id);
+    }
+    data.persist()?;
+    drop(data);
+
+    let reply = if enabled {
+        "You'll be reminded about elections you haven't voted in."
+    } else {
+        "You won't be sent election reminders."
+    };
+    ctx.send(CreateReply::default().ephemeral(true).content(reply))
+        .await?;
+    Ok(())
+}
+
+async fn autocomplete_voted(ctx: Context<'_>, partial: &str) -> Vec<serenity::AutocompleteChoice> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Vec::new();
+    };
+    let mut data = ctx.data().write().await;
+    let guild = data.guild_mut(guild_id).latest();
+    let mut elections: Vec<_> = guild
+        .elections
+        .elections
+        .iter()
+        .filter(|(id, e)| e.has_voted(ctx.author().id) && id.to_string().starts_with(partial))
+        .map(|(id, _)| usize::from(*id))
+        .collect();
+    elections.sort_by_key(|id| std::cmp::Reverse(*id));
+    elections
+        .into_iter()
+        .map(|id| serenity::AutocompleteChoice::new(format!("Election {id}"), id))
+        .collect()
+}
+
+/// Check that the ballot recorded for you is the one your receipt was issued for
+#[poise::command(slash_command, guild_only = true, rename = "verify-ballot")]
+async fn verify_ballot(
+    ctx: Context<'_>,
+    #[description = "Election you voted in"]
+    #[autocomplete = "autocomplete_voted"]
+    election: usize,
+    #[description = "The receipt you were given after voting"] receipt: String,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow::anyhow!("No guild id. Must be in a guild"))?;
+    let key = ballot_key().ok_or_else(|| {
+        anyhow!(
+            "Receipts need {} to be configured",
+            election::BallotKey::VAR
+        )
+    })?;
+    let election_id = actions::ElectionId::from(election);
+    let mut data = ctx.data().write().await;
+    let guild = data.guild_mut(guild_id).latest();
+    let election = guild
+        .elections
+        .elections
+        .get(&election_id)
+        .ok_or_else(|| anyhow!("There is no election {election_id}"))?;
+    if !election.has_voted(ctx.author().id) {
+        return Err(anyhow!("You haven't voted in election {election_id}"));
+    }
+    let Some(ballot) =
+        election.check_receipt(election_id.into(), ctx.author().id, &receipt, key)?
+    else {
+        let mut problem = "The ballot recorded for you doesn't match that receipt. If you voted \
+                           again since, use the receipt you were given last."
+            .to_owned();
+        let removed = election.removed_candidates();
+        if !removed.is_empty() {
+            let removed: Vec<String> = removed.iter().map(|n| format!("**{n}**")).collect();
+            problem += &format!(
+                " Receipts also stop matching when a candidate on the ballot is removed, and \
+                 this election removed {}.",
+                removed.join(", ")
+            );
+        }
+        return Err(anyhow!(problem));
+    };
+    let mut embed = ballot.make_embed(election);
+    embed = match election.verify_log() {
+        Ok(()) => embed.field(
+            "Ballot log",
+            format!("Intact, ending `{}`", election.log_head()),
+            false,
+        ),
+        Err(problem) => embed.field("Ballot log", format!("⚠️ {problem}"), false),
+    };
+    let reply = CreateReply::default()
+        .ephemeral(true)
+        .content("Your ballot is recorded as you cast it.")
+        .embed(embed);
+    drop(data);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Change how many offices a region must or may hold
+#[poise::command(slash_command, guild_only = true, rename = "set-quota")]
+async fn set_quota(
+    ctx: Context<'_>,
+    #[description = "Election to change"]
+    #[autocomplete = "autocomplete_election"]
+    election: usize,
+    region: String,
+    #[description = "Offices reserved (e.g. 2) or min-max (e.g. 0-1). 0 removes the quota"]
+    quota: String,
+) -> Result<(), anyhow::Error> {
+    edit_election(ctx, election, |election| {
+        let (region, quota) = election::parse_quota(&format!("{region}:{quota}"))?;
+        election.set_quota(region.clone(), quota)?;
+        Ok(format!("Updated the quota for {region}"))
+    })
+    .await
+}
+
+/// Stop accepting ballots and publish the results
+#[poise::command(slash_command, guild_only = true)]
+async fn close(
+    ctx: Context<'_>,
+    #[description = "Election to close"]
+    #[autocomplete = "autocomplete_election"]
+    election: usize,
+) -> Result<(), anyhow::Error> {
+    edit_election(ctx, election, |election| {
+        if !election.close() {
+            return Err(anyhow!("Voting has already closed"));
+        }
+        Ok("Voting has closed and the results are published.".into())
+    })
+    .await
+}
+
+/// Accept ballots again on a closed election
+#[poise::command(slash_command, guild_only = true)]
+async fn reopen(
+    ctx: Context<'_>,
+    #[description = "Election to reopen"]
+    #[autocomplete = "autocomplete_election"]
+    election: usize,
+    #[description = "When voting closes, e.g. 2024-06-08 18:00 (UTC)"] closes_at: Option<String>,
+) -> Result<(), anyhow::Error> {
+    edit_election(ctx, election, |election| {
+        let closes_at = closes_at.as_deref().map(election::parse_time).transpose()?;
+        election.reopen(closes_at, Utc::now())?;
+        Ok("Voting has reopened.".into())
+    })
+    .await
+}
+
+/// Delete an election, its ballots and its message
+#[poise::command(slash_command, guild_only = true)]
+async fn delete(
+    ctx: Context<'_>,
+    #[description = "Election to delete"]
+    #[autocomplete = "autocomplete_election"]
+    election: usize,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow::anyhow!("No guild id. Must be in a guild"))?;
+    let election_id = actions::ElectionId::from(election);
+    let mut data = ctx.data().write().await;
+    let guild = data.guild_mut(guild_id).latest();
+    let owner = guild
+        .elections
+        .elections
+        .get(&election_id)
+        .map(|e| *e.owner())
+        .ok_or_else(|| anyhow!("There is no election {election_id}"))?;
+    if owner != ctx.author().id {
+        return Err(anyhow!("Only the creator of an election can delete it"));
+    }
+
+    let election = guild
+        .elections
+        .elections
+        .remove(&election_id)
+        .expect("election was just found");
+    guild.votes.votes.retain(|_, v| v.election != election_id);
+    data.persist()?;
+    if let Some((channel, message)) = election.message() {
+        if let Err(e) = channel.delete_message(ctx, message).await {
+            warn!("Could not delete election {election_id}'s message: {e:?}");
+        }
+    }
+
+    ctx.send(
+        CreateReply::default()
+            .ephemeral(true)
+            .content(format!("Deleted election {election_id}")),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Buttons for the election message. Voting is only offered until the election closes.
+fn election_components(
+    election_id: actions::ElectionId,
+    state: election::State,
+) -> Vec<CreateActionRow> {
+    let mut buttons = Vec::new();
+    if matches!(state, election::State::Draft | election::State::Open) {
+        buttons.push(
             Action::Election(ElectionAction {
                 election_id,
                 ty: ElectionActionType::InitiateVote,
             })
             .button(),
-            Action::Election(ElectionAction {
-                election_id,
-                ty: ElectionActionType::GetResult,
-            })
-            .button(),
-        ])]);
-    ctx.send(reply).await?;
-    guild.elections.elections.insert(election_id, election);
-    data.persist("elections")?;
+        );
+    }
+    buttons.push(
+        Action::Election(ElectionAction {
+            election_id,
+            ty: ElectionActionType::GetResult,
+        })
+        .button(),
+    );
+    vec![CreateActionRow::Buttons(buttons)]
+}
 
+/// Brings the election message up to date, adding the winners once voting has closed.
+fn election_edit(
+    election_id: actions::ElectionId,
+    election: &election::Election,
+) -> serenity::EditMessage {
+    let mut embeds = vec![election.make_embed()];
+    if matches!(
+        election.state(),
+        election::State::Closed | election::State::Certified
+    ) {
+        embeds.push(election.outcome().make_announcement(election));
+    }
+    serenity::EditMessage::new()
+        .embeds(embeds)
+        .components(election_components(election_id, election.state()))
+}
+
+/// The post for the election's announcement channel, if it has one.
+fn announcement(
+    election: &election::Election,
+) -> Option<(serenity::ChannelId, serenity::CreateMessage)> {
+    let channel = election.announce_in()?;
+    let content = match election.message() {
+        Some((election_channel, message)) => format!(
+            "Voting has closed in {}",
+            message.link(election_channel, None)
+        ),
+        None => "Voting has closed in the TEA House Moderator Election".into(),
+    };
+    let message = serenity::CreateMessage::new()
+        .content(content)
+        .embed(election.outcome().make_announcement(election));
+    Some((channel, message))
+}
+
+/// Shows the winners on the election message and in the announcement channel, recording that
+/// they were announced.
+async fn publish_results(
+    ctx: &serenity::Context,
+    election_id: actions::ElectionId,
+    election: &mut election::Election,
+) -> Result<(), anyhow::Error> {
+    refresh_election(ctx, election_id, election).await?;
+    if let Some((channel, message)) = announcement(election) {
+        channel.send_message(ctx, message).await?;
+        election.set_announced();
+    }
     Ok(())
 }
 
-fn vote_menu<VID: Into<actions::VoteId>>(vote_id: VID) -> Vec<CreateActionRow> {
+fn vote_menu<VID: Into<actions::VoteId>>(
+    vote_id: VID,
+    kind: election::ElectionKind,
+    scale: election::Scale,
+) -> Vec<CreateActionRow> {
     let vote_id = vote_id.into();
+    if kind == election::ElectionKind::Approval {
+        return vec![CreateActionRow::Buttons(vec![
+            Action::Vote(VoteAction {
+                vote_id,
+                ty: VoteActionType::Approve,
+            })
+            .button(),
+            Action::Vote(VoteAction {
+                vote_id,
+                ty: VoteActionType::Reject,
+            })
+            .button(),
+            Action::Vote(VoteAction {
+                vote_id,
+                ty: VoteActionType::VoidBallot,
+            })
+            .button(),
+        ])];
+    }
+
     vec![
         CreateActionRow::SelectMenu(CreateSelectMenu::new(
             Action::Vote(VoteAction {
@@ -347,13 +1361,10 @@ fn vote_menu<VID: Into<actions::VoteId>>(vote_id: VID) -> Vec<CreateActionRow> {
                 ty: VoteActionType::SelectVote,
             }),
             CreateSelectMenuKind::String {
-                options: vec![
-                    CreateSelectMenuOption::new("1 (least desired)", "1"),
-                    CreateSelectMenuOption::new("2", "2"),
-                    CreateSelectMenuOption::new("3", "3"),
-                    CreateSelectMenuOption::new("4", "4"),
-                    CreateSelectMenuOption::new("5 (most desired)", "5"),
-                ],
+                options: scale
+                    .options()
+                    .map(|(label, rank)| CreateSelectMenuOption::new(label, rank.to_string()))
+                    .collect(),
             },
         )),
         CreateActionRow::Buttons(vec![
@@ -371,6 +1382,17 @@ fn vote_menu<VID: Into<actions::VoteId>>(vote_id: VID) -> Vec<CreateActionRow> {
     ]
 }
 
+/// What the eligibility rules need to know about the member who pressed a button.
+fn voter(interaction: &serenity::ComponentInteraction) -> election::Voter {
+    let time = |t: serenity::Timestamp| DateTime::from_timestamp(t.unix_timestamp(), 0);
+    let member = interaction.member.as_ref();
+    election::Voter {
+        roles: member.map(|m| m.roles.clone()).unwrap_or_default(),
+        joined_at: member.and_then(|m| m.joined_at).and_then(time),
+        created_at: time(interaction.user.created_at()).unwrap_or_default(),
+    }
+}
+
 async fn initiate_vote(
     ctx: &serenity::Context,
     action: actions::Action,
@@ -386,16 +1408,64 @@ async fn initiate_vote(
         actions::Action::Election(actions::ElectionAction {
             election_id: id,
             ty: actions::ElectionActionType::InitiateVote,
-        }) => (guild.votes.start(id, interaction), false),
+        }) => {
+            let secret = guild.elections.get(action, &guild.votes)?.is_secret();
+            (guild.votes.start(id, interaction, secret), false)
+        }
         actions::Action::Vote(actions::VoteAction {
             vote_id: id,
             ty: actions::VoteActionType::ConfirmInitiateVote,
         }) => (id, true),
         _ => return Err(anyhow!("Invalid action for initiate_vote: {action:?}")),
     };
+    let state = guild.elections.get(action, &guild.votes)?.state();
+    if state != election::State::Open {
+        guild.votes.remove(vote_id);
+        interaction
+            .create_response(
+                ctx,
+                CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new()
+                        .ephemeral(true)
+                        .content(match state {
+                            election::State::Draft => "Voting hasn't opened yet.",
+                            _ => "Voting has closed.",
+                        })
+                        .components(vec![]),
+                ),
+            )
+            .await?;
+        return Ok(());
+    }
+    let problems = guild.elections.get(action, &guild.votes)?.voting_problems(
+        interaction.user.id,
+        &voter(interaction),
+        Utc::now(),
+    );
+    if !problems.is_empty() {
+        guild.votes.remove(vote_id);
+        interaction
+            .create_response(
+                ctx,
+                CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new()
+                        .ephemeral(true)
+                        .content(format!(
+                            "You can't vote in this election.\n* {}",
+                            problems.join("\n* ")
+                        ))
+                        .components(vec![]),
+                ),
+            )
+            .await?;
+        return Ok(());
+    }
     let election = guild.elections.get_mut(action, &guild.votes)?;
 
-    if election.ballots.contains_key(&interaction.user.id) && !confirmed {
+    if election.has_voted(interaction.user.id) && !confirmed {
+        let ballot = election
+            .ballot_of(interaction.user.id, ballot_key())?
+            .map(|b| b.make_embed(election));
         interaction
             .create_response(
                 ctx,
@@ -406,7 +1476,7 @@ async fn initiate_vote(
                             "You have already submitted a ballot. \
                             Voting again will overwrite your existing votes. Is this okay?",
                         )
-                        .add_embed(election.ballots[&interaction.user.id].make_embed())
+                        .add_embeds(ballot.into_iter().collect())
                         .button(
                             actions::Action::Vote(actions::VoteAction {
                                 vote_id,
@@ -425,13 +1495,14 @@ async fn initiate_vote(
             )
             .await?
     } else {
-        let _: Option<_> = election.ballots.remove(&interaction.user.id);
-        let (name, region) = election
-            .candidates
-            .iter()
+        election.void(interaction.user.id, ballot_key(), Utc::now())?;
+        let (kind, scale) = (election.kind(), election.scale());
+        let (name, candidate) = election
+            .ballot_candidates(interaction.user.id)
             .next()
             .ok_or_else(|| anyhow!("No candidates!"))?;
-        let content = format!("# Please vote for the candidate\n{name} (Region: {region})");
+        let content = "# Please vote for the candidate";
+        let prompt = candidate_prompt(ctx, name, candidate).await;
         match action {
             actions::Action::Election(_) => {
                 interaction
@@ -441,7 +1512,8 @@ async fn initiate_vote(
                             CreateInteractionResponseMessage::new()
                                 .ephemeral(true)
                                 .content(content)
-                                .components(vote_menu(vote_id)),
+                                .embed(prompt)
+                                .components(vote_menu(vote_id, kind, scale)),
                         ),
                     )
                     .await?;
@@ -454,8 +1526,8 @@ async fn initiate_vote(
                         interaction,
                         EditInteractionResponse::new()
                             .content(content)
-                            .embeds(vec![])
-                            .components(vote_menu(vote_id)),
+                            .embeds(vec![prompt])
+                            .components(vote_menu(vote_id, kind, scale)),
                     )
                     .await?
             }
@@ -465,6 +1537,25 @@ async fn initiate_vote(
     Ok(())
 }
 
+/// The vote prompt for a candidate, with the avatar of their Discord account if they have one.
+async fn candidate_prompt(
+    ctx: &serenity::Context,
+    name: &election::Name,
+    candidate: &election::Candidate,
+) -> serenity::CreateEmbed {
+    let avatar = match candidate.user() {
+        Some(user) => match user.to_user(ctx).await {
+            Ok(user) => Some(user.face()),
+            Err(e) => {
+                warn!("Couldn't fetch {user} for {name}'s avatar: {e}");
+                None
+            }
+        },
+        None => None,
+    };
+    candidate.make_embed(name, avatar)
+}
+
 async fn select_vote(
     ctx: &serenity::Context,
     action: actions::VoteAction,
@@ -476,11 +1567,12 @@ async fn select_vote(
         .ok_or_else(|| anyhow::anyhow!("No guild id. Must be in a guild"))?;
     let guild = data.guild_mut(guild_id);
     let guild = guild.latest();
-    let election = guild.elections.get_mut(action, &guild.votes)?;
+    let election = guild.elections.get(action, &guild.votes)?;
+    let (kind, scale) = (election.kind(), election.scale());
     let vote = guild.votes.get_mut(action)?;
     let mut needs_vote = false;
     let mut vote_registered = false;
-    for (name, region) in &election.candidates {
+    for (name, candidate) in election.ballot_candidates(interaction.user.id) {
         if !vote.partial_ballot.votes.contains_key(name) {
             if !vote_registered {
                 vote_registered = true;
@@ -489,11 +1581,19 @@ async fn select_vote(
                 {
                     let rank = values[0].parse()?;
                     vote.partial_ballot.votes.insert(name.clone(), rank);
-                } else if action.ty == actions::VoteActionType::SkipVote {
-                    vote.partial_ballot.votes.insert(name.clone(), 0);
+                } else {
+                    match action.ty {
+                        actions::VoteActionType::Approve => {
+                            vote.partial_ballot.votes.insert(name.clone(), 1);
+                        }
+                        actions::VoteActionType::SkipVote | actions::VoteActionType::Reject => {
+                            vote.partial_ballot.votes.insert(name.clone(), 0);
+                        }
+                        _ => {}
+                    }
                 }
             } else {
-                let content = format!("# Please vote for the candidate\n{name} (Region: {region})");
+                let prompt = candidate_prompt(ctx, name, candidate).await;
                 needs_vote = true;
                 guild
                     .edit_response(
@@ -501,8 +1601,9 @@ async fn select_vote(
                         action,
                         interaction,
                         EditInteractionResponse::new()
-                            .content(content)
-                            .components(vote_menu(action)),
+                            .content("# Please vote for the candidate")
+                            .embeds(vec![prompt])
+                            .components(vote_menu(action, kind, scale)),
                     )
                     .await?;
                 break;
@@ -511,17 +1612,25 @@ async fn select_vote(
     }
 
     if !needs_vote {
+        let receipt = guild.votes.save_ballot(action, &mut guild.elections)?;
+        let content = match receipt {
+            Some(receipt) => format!(
+                "Thank you for voting! Your receipt is `{receipt}`. Keep it to check your ballot \
+                 was counted with `/verify-ballot`."
+            ),
+            None => "Thank you for voting!".into(),
+        };
         guild
             .edit_response(
                 ctx,
                 action,
                 interaction,
                 EditInteractionResponse::new()
-                    .content("Thank you for voting!")
+                    .content(content)
+                    .embeds(vec![])
                     .components(vec![]),
             )
             .await?;
-        guild.votes.save_ballot(action, &mut guild.elections)?;
         guild.update_election(ctx, action, interaction).await?;
         guild.votes.remove(action);
         return Ok(());
@@ -544,7 +1653,7 @@ async fn stop_vote(
     let election = guild.elections.get_mut(action, &guild.votes)?;
 
     if action.ty == actions::VoteActionType::VoidBallot {
-        let _: Option<_> = election.ballots.remove(&interaction.user.id);
+        election.void(interaction.user.id, ballot_key(), Utc::now())?;
         guild
             .edit_response(
                 ctx,
@@ -573,19 +1682,15 @@ async fn get_result(
     ctx: &serenity::Context,
     action: actions::ElectionAction,
     interaction: &serenity::ComponentInteraction,
-    data: &RwLockReadGuard<'_, data::GlobalData<Elections>>,
+    data: &mut RwLockWriteGuard<'_, data::GlobalData<Elections>>,
 ) -> Result<(), anyhow::Error> {
     let guild_id = interaction
         .guild_id
         .ok_or_else(|| anyhow::anyhow!("No guild id. Must be in a guild"))?;
-    let guild = data
-        .guild(guild_id)
-        .ok_or_else(|| anyhow!("No guild data for this ID"))?;
-    let guild = guild
-        .try_latest()
-        .ok_or_else(|| anyhow!("Guild data hasn't been upgraded"))?;
+    let guild = data.guild_mut(guild_id);
+    let guild = guild.latest();
 
-    let election = guild.elections.get(action, &guild.votes)?;
+    let election = guild.elections.get_mut(action, &guild.votes)?;
     if *election.owner() != interaction.user.id {
         interaction
             .create_response(
@@ -600,32 +1705,470 @@ async fn get_result(
         return Ok(());
     }
 
+    if matches!(
+        election.state(),
+        election::State::Draft | election::State::Open
+    ) {
+        interaction
+            .create_response(
+                ctx,
+                CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new()
+                        .ephemeral(true)
+                        .content("Results are available once voting closes.")
+                        .button(
+                            Action::Election(ElectionAction {
+                                ty: ElectionActionType::Close,
+                                ..action
+                            })
+                            .button(),
+                        ),
+                ),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    // Once the owner has seen a result its tie-breaks must not change.
+    election.seal_seed();
+    let election = &*election;
+
+    let mut message = CreateInteractionResponseMessage::new()
+        .ephemeral(true)
+        .embed(election.outcome().make_embed(election));
+    if election.method() == election::CountingMethod::Stv {
+        message = message.embed(stv_embed(&election.stv()));
+    }
+    if election.state() == election::State::Closed {
+        message = message.button(
+            Action::Election(ElectionAction {
+                ty: ElectionActionType::Certify,
+                ..action
+            })
+            .button(),
+        );
+    }
+    if election.check_announce().is_ok() {
+        message = message.button(
+            Action::Election(ElectionAction {
+                ty: ElectionActionType::Announce,
+                ..action
+            })
+            .button(),
+        );
+    }
+
+    interaction
+        .create_response(ctx, CreateInteractionResponse::Message(message))
+        .await?;
+
+    Ok(())
+}
+
+/// Closes, certifies or announces an election for its owner.
+async fn change_state(
+    ctx: &serenity::Context,
+    action: actions::ElectionAction,
+    interaction: &serenity::ComponentInteraction,
+    data: &mut RwLockWriteGuard<'_, data::GlobalData<Elections>>,
+) -> Result<(), anyhow::Error> {
+    let guild_id = interaction
+        .guild_id
+        .ok_or_else(|| anyhow::anyhow!("No guild id. Must be in a guild"))?;
+    let guild = data.guild_mut(guild_id);
+    let guild = guild.latest();
+
+    let election = guild.elections.get_mut(action, &guild.votes)?;
+    if *election.owner() != interaction.user.id {
+        interaction
+            .create_response(
+                ctx,
+                CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new()
+                        .ephemeral(true)
+                        .content("Only the creator of an election can change its state"),
+                ),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let (content, announce) = match action.ty {
+        ElectionActionType::Close if election.close() => (
+            "Voting has closed and the results are published.".to_owned(),
+            true,
+        ),
+        ElectionActionType::Close => ("Voting had already closed.".into(), false),
+        ElectionActionType::Certify => {
+            if let Err(e) = election.certify() {
+                interaction
+                    .create_response(
+                        ctx,
+                        CreateInteractionResponse::Message(
+                            CreateInteractionResponseMessage::new()
+                                .ephemeral(true)
+                                .content(e.to_string()),
+                        ),
+                    )
+                    .await?;
+                return Ok(());
+            }
+            let content = assign_winner_role(ctx, guild_id, election).await;
+            interaction
+                .create_response(
+                    ctx,
+                    CreateInteractionResponse::UpdateMessage(
+                        CreateInteractionResponseMessage::new()
+                            .content(content)
+                            .components(vec![]),
+                    ),
+                )
+                .await?;
+            refresh_election(ctx, action.election_id, election).await?;
+            return Ok(());
+        }
+        ElectionActionType::Announce => {
+            if let Err(e) = election.check_announce() {
+                interaction
+                    .create_response(
+                        ctx,
+                        CreateInteractionResponse::Message(
+                            CreateInteractionResponseMessage::new()
+                                .ephemeral(true)
+                                .content(e.to_string()),
+                        ),
+                    )
+                    .await?;
+                return Ok(());
+            }
+            ("The results are published.".into(), true)
+        }
+        _ => return Err(anyhow!("Invalid action for change_state: {action:?}")),
+    };
     interaction
         .create_response(
             ctx,
-            CreateInteractionResponse::Message(
+            CreateInteractionResponse::UpdateMessage(
                 CreateInteractionResponseMessage::new()
-                    .ephemeral(true)
-                    .content(match election.run() {
-                        Some(list) => format!(
-                            "{} votes total\n\nThe following candidates have been elected:\n{}",
-                            election.ballots.len(),
-                            list.into_iter()
-                                .map(|c| format!("* **{c}**"))
-                                .collect::<Vec<_>>()
-                                .join("\n")
-                        ),
-                        None => "Election did not complete. Likely there were not enough \
-                            candidates to fill the required offices."
-                            .into(),
-                    }),
+                    .content(content)
+                    .components(vec![]),
             ),
         )
         .await?;
+    if announce {
+        publish_results(ctx, action.election_id, election).await?;
+    } else {
+        refresh_election(ctx, action.election_id, election).await?;
+    }
+
+    Ok(())
+}
+
+/// Gives the election's winner role to every winner with a Discord account and, if the election
+/// replaces role holders, takes it from everyone else. Describes what was done.
+async fn assign_winner_role(
+    ctx: &serenity::Context,
+    guild_id: serenity::GuildId,
+    election: &election::Election,
+) -> String {
+    let Some(role) = election.winner_role() else {
+        return "The results are certified.".into();
+    };
+    let winners = election.winners();
+    let mut failed = Vec::new();
+    for &user in &winners {
+        if let Err(e) = ctx
+            .http
+            .add_member_role(guild_id, user, role, Some("Elected"))
+            .await
+        {
+            warn!("Could not give {user} the winner role: {e}");
+            failed.push(format!("couldn't be given to <@{user}>"));
+        }
+    }
+    // An election without winners mustn't strip the role from everyone.
+    if election.replace_role_holders() && !winners.is_empty() {
+        match role_holders(ctx, guild_id, role).await {
+            Ok(holders) => {
+                for user in election.outgoing_holders(holders) {
+                    if let Err(e) = ctx
+                        .http
+                        .remove_member_role(guild_id, user, role, Some("Not re-elected"))
+                        .await
+                    {
+                        warn!("Could not take the winner role from {user}: {e}");
+                        failed.push(format!("couldn't be taken from <@{user}>"));
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Could not list the holders of {role}: {e}");
+                failed.push("couldn't be taken from its previous holders".into());
+            }
+        }
+    }
+    if failed.is_empty() {
+        format!("The results are certified and the winners were given <@&{role}>.")
+    } else {
+        format!(
+            "The results are certified, but <@&{role}> {}.",
+            failed.join(", ")
+        )
+    }
+}
+
+/// Members of the guild who hold `role`.
+async fn role_holders(
+    ctx: &serenity::Context,
+    guild_id: serenity::GuildId,
+    role: serenity::RoleId,
+) -> Result<Vec<serenity::UserId>, anyhow::Error> {
+    // Discord returns at most 1000 members per request.
+    const PAGE: u64 = 1000;
+    let mut holders = Vec::new();
+    let mut after = None;
+    loop {
+        let members = guild_id.members(&ctx.http, Some(PAGE), after).await?;
+        holders.extend(
+            members
+                .iter()
+                .filter(|m| m.roles.contains(&role))
+                .map(|m| m.user.id),
+        );
+        match members.last() {
+            Some(last) if members.len() as u64 == PAGE => after = Some(last.user.id),
+            _ => return Ok(holders),
+        }
+    }
+}
+
+/// Shows the election's current state on its message.
+async fn refresh_election(
+    ctx: &serenity::Context,
+    election_id: actions::ElectionId,
+    election: &election::Election,
+) -> Result<(), anyhow::Error> {
+    if let Some((channel, message)) = election.message() {
+        channel
+            .edit_message(ctx, message, election_edit(election_id, election))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Opens and closes elections when their scheduled times pass.
+async fn run_schedule(ctx: serenity::Context, data: GlobalState<Elections>) {
+    let (reminders, queue) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(send_reminders(ctx.clone(), queue));
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(30));
+    loop {
+        interval.tick().await;
+        if let Err(e) = advance_elections(&ctx, &data, &reminders).await {
+            warn!("Could not advance elections: {e:?}");
+        }
+    }
+}
+
+async fn advance_elections(
+    ctx: &serenity::Context,
+    state: &GlobalState<Elections>,
+    reminder_queue: &tokio::sync::mpsc::UnboundedSender<Reminder>,
+) -> Result<(), anyhow::Error> {
+    let mut data = state.write().await;
+    let now = Utc::now();
+    let mut changed = false;
+    let mut edits = Vec::new();
+    let mut announcements = Vec::new();
+    let mut snapshots = Vec::new();
+    let mut reminders = Vec::new();
+    for (guild_id, guild) in data.guilds_mut() {
+        let guild = guild.latest();
+        for (id, election) in guild.elections.elections.iter_mut() {
+            if election.advance(now) {
+                tracing::info!("Election moved to {}", election.state());
+                changed = true;
+                edits.extend(
+                    election
+                        .message()
+                        .map(|m| (m, election_edit(*id, election))),
+                );
+            }
+            if election.announcement_pending() {
+                announcements
+                    .extend(announcement(election).map(|message| (guild_id, *id, message)));
+            }
+            if let Some(role) = election.pending_snapshot() {
+                snapshots.push((guild_id, *id, role));
+            }
+            if election.due_reminder(now).is_some() {
+                let recipients: Vec<_> = election
+                    .non_voters()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|u| !guild.reminder_opt_outs.contains(u))
+                    .collect();
+                reminders.push((
+                    guild_id,
+                    *id,
+                    Reminder {
+                        recipients,
+                        content: reminder(guild_id, election),
+                    },
+                ));
+            }
+        }
+    }
+    if !changed && snapshots.is_empty() && announcements.is_empty() && reminders.is_empty() {
+        return Ok(());
+    }
+    if changed {
+        data.persist()?;
+    }
+    drop(data);
+
+    // Members are listed without holding the lock. Nobody can vote until the roll is filled.
+    // A failed listing leaves the snapshot pending, so it is tried again on the next tick.
+    for (guild_id, id, role) in snapshots {
+        let members = match role_holders(ctx, guild_id, role).await {
+            Ok(members) => members,
+            Err(e) => {
+                warn!("Couldn't list the members of {role} for election {id}'s roll: {e}");
+                continue;
+            }
+        };
+        let mut data = state.write().await;
+        let guild = data.guild_mut(guild_id).latest();
+        let Some(election) = guild.elections.elections.get_mut(&id) else {
+            continue;
+        };
+        if election.pending_snapshot() == Some(role) {
+            election.snapshot_roll(members.into_iter().collect());
+            edits.extend(election.message().map(|m| (m, election_edit(id, election))));
+            if let Err(e) = data.persist() {
+                warn!("Couldn't save election {id}'s roll: {e:?}");
+            }
+        }
+    }
 
+    // One missing message or channel mustn't hold up the rest. Announcements stay pending until
+    // they are posted, so failed ones are tried again on the next tick.
+    for ((channel, message), edit) in edits {
+        if let Err(e) = channel.edit_message(ctx, message, edit).await {
+            warn!("Couldn't update election message {message}: {e}");
+        }
+    }
+    for (guild_id, id, (channel, message)) in announcements {
+        if let Err(e) = channel.send_message(ctx, message).await {
+            warn!("Couldn't announce the results of election {id} in {channel}: {e}");
+            continue;
+        }
+        let mut data = state.write().await;
+        let guild = data.guild_mut(guild_id).latest();
+        if let Some(election) = guild.elections.elections.get_mut(&id) {
+            election.set_announced();
+            if let Err(e) = data.persist() {
+                warn!("Couldn't save that election {id} was announced: {e:?}");
+            }
+        }
+    }
+    // Reminders are only marked sent once queued, so failed ones are tried again.
+    for (guild_id, id, reminder) in reminders {
+        if !reminder.recipients.is_empty() {
+            if let Err(e) = reminder_queue.send(reminder) {
+                warn!("Couldn't queue the reminder for election {id}: {e}");
+                continue;
+            }
+        }
+        let mut data = state.write().await;
+        let guild = data.guild_mut(guild_id).latest();
+        if let Some(election) = guild.elections.elections.get_mut(&id) {
+            election.mark_reminded(now);
+            if let Err(e) = data.persist() {
+                warn!("Couldn't save that election {id}'s reminder was sent: {e:?}");
+            }
+        }
+    }
     Ok(())
 }
 
+/// A DM to send to members who haven't voted.
+struct Reminder {
+    recipients: Vec<serenity::UserId>,
+    content: String,
+}
+
+/// The reminder DM for an election.
+fn reminder(guild_id: serenity::GuildId, election: &election::Election) -> String {
+    let closes = election
+        .closes_at()
+        .map(|t| format!(" closes <t:{}:R>", t.timestamp()))
+        .unwrap_or_default();
+    let link = election
+        .message()
+        .map(|(channel, message)| format!(" Vote at {}", message.link(channel, Some(guild_id))))
+        .unwrap_or_default();
+    format!(
+        "You haven't voted yet and the TEA House Moderator Election{closes}.{link}\n\
+        -# Use `/election reminders` in the server to stop these messages."
+    )
+}
+
+/// Sends queued reminder DMs one at a time, slowly enough not to look like spam to Discord.
+async fn send_reminders(
+    ctx: serenity::Context,
+    mut queue: tokio::sync::mpsc::UnboundedReceiver<Reminder>,
+) {
+    const DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+    while let Some(Reminder {
+        recipients,
+        content,
+    }) = queue.recv().await
+    {
+        for user in recipients {
+            let message = serenity::CreateMessage::new().content(&content);
+            if let Err(e) = user.direct_message(&ctx, message).await {
+                warn!("Could not remind {user}: {e}");
+            }
+            tokio::time::sleep(DELAY).await;
+        }
+    }
+}
+
+fn stv_embed(count: &election::stv::Count) -> serenity::CreateEmbed {
+    use election::stv::Event;
+
+    serenity::CreateEmbed::new()
+        .title("Count by round")
+        .color(serenity::Color::BLURPLE)
+        .field("Quota", format!("{:.2}", count.quota), true)
+        .description(
+            count
+                .rounds
+                .iter()
+                .enumerate()
+                .map(|(i, round)| {
+                    let votes = |name| round.tallies.get(name).copied().unwrap_or(0.);
+                    let event = match &round.event {
+                        Event::Elected { name, surplus } => format!(
+                            "**{name}** elected ({} office) with {:.2} votes, \
+                            {surplus:.2} surplus transferred",
+                            count.seated[name],
+                            votes(name)
+                        ),
+                        Event::Eliminated(name) => {
+                            format!("**{name}** eliminated with {:.2} votes", votes(name))
+                        }
+                        Event::Excluded { name, .. } => {
+                            format!("**{name}** excluded, no eligible office remains")
+                        }
+                    };
+                    format!("{}. {event} ({:.2} exhausted)", i + 1, round.exhausted)
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+}
+
 async fn event_handler(
     ctx: &serenity::Context,
     event: &serenity::FullEvent,
@@ -642,28 +2185,39 @@ async fn event_handler(
                     actions::VoteActionType::ConfirmInitiateVote => {
                         let mut data = data.write().await;
                         initiate_vote(ctx, action, interaction, &mut data).await?;
-                        data.persist("elections")?;
+                        data.persist()?;
                     }
-                    actions::VoteActionType::SelectVote | actions::VoteActionType::SkipVote => {
+                    actions::VoteActionType::SelectVote
+                    | actions::VoteActionType::SkipVote
+                    | actions::VoteActionType::Approve
+                    | actions::VoteActionType::Reject => {
                         let mut data = data.write().await;
                         select_vote(ctx, vote_action, interaction, &mut data).await?;
-                        data.persist("elections")?;
+                        data.persist()?;
                     }
                     actions::VoteActionType::CancelVote | actions::VoteActionType::VoidBallot => {
                         let mut data = data.write().await;
                         stop_vote(ctx, vote_action, interaction, &mut data).await?;
-                        data.persist("elections")?;
+                        data.persist()?;
                     }
                 },
                 actions::Action::Election(election_action) => match election_action.ty {
                     actions::ElectionActionType::InitiateVote => {
                         let mut data = data.write().await;
                         initiate_vote(ctx, action, interaction, &mut data).await?;
-                        data.persist("elections")?;
+                        data.persist()?;
                     }
                     actions::ElectionActionType::GetResult => {
-                        let data = data.read().await;
-                        get_result(ctx, election_action, interaction, &data).await?;
+                        let mut data = data.write().await;
+                        get_result(ctx, election_action, interaction, &mut data).await?;
+                        data.persist()?;
+                    }
+                    actions::ElectionActionType::Close
+                    | actions::ElectionActionType::Certify
+                    | actions::ElectionActionType::Announce => {
+                        let mut data = data.write().await;
+                        change_state(ctx, election_action, interaction, &mut data).await?;
+                        data.persist()?;
                     }
                 },
             }
@@ -674,6 +2228,44 @@ async fn event_handler(
     Ok(())
 }
 
+/// Stops the bot on ctrl-c or SIGTERM, once the data has been written out.
+async fn shut_down(
+    shard_manager: std::sync::Arc<serenity::ShardManager>,
+    state: GlobalState<Elections>,
+) {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!("Can't listen for SIGTERM: {e:?}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => {
+            if let Err(e) = result {
+                warn!("Can't listen for ctrl-c: {e:?}");
+                return;
+            }
+        }
+        _ = terminate => {}
+    }
+
+    tracing::info!("Shutting down");
+    // Holding the lock until the end keeps commands from changing the data after it's written.
+    let mut data = state.write().await;
+    if let Err(e) = tokio::task::block_in_place(|| data.flush()) {
+        tracing::error!("Couldn't save before shutting down: {e:?}");
+    }
+    shard_manager.shutdown_all().await;
+}
+
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     let appender = tracing_appender::rolling::RollingFileAppender::builder()
@@ -709,13 +2301,34 @@ async fn main() -> Result<(), anyhow::Error> {
     tracing::subscriber::set_global_default(subscriber).context("subscriber setup")?;
 
     dotenv::dotenv().context("loading dotenv")?;
+    let _ = BALLOT_KEY.set(election::BallotKey::from_env());
+    let data_key = data::DataKey::from_env(data::DataKey::VAR)?;
+
+    // Saved in a SQLite database at this path if set, or in elections.json otherwise.
+    let database = std::env::var("DATABASE").ok().filter(|d| !d.is_empty());
+
+    // `rotate-data-key` re-encrypts the data file and backups with NEW_DATA_KEY, then exits.
+    if std::env::args().nth(1).as_deref() == Some("rotate-data-key") {
+        let new_key = data::DataKey::from_env(data::DataKey::NEW_VAR)?;
+        let mut count = data::rotate_key("elections", data_key.as_ref(), new_key.as_ref())?;
+        if let Some(database) = &database {
+            count += data::rotate_database_key(database, data_key.as_ref(), new_key.as_ref())?;
+        }
+        tracing::info!(
+            "Rewrote {count} data files. Set {} to the new key before restarting.",
+            data::DataKey::VAR
+        );
+        return Ok(());
+    }
 
     let token = std::env::var("DISCORD_TOKEN")?;
-    let intents = serenity::GatewayIntents::non_privileged();
+    // Listing role holders needs the privileged members intent.
+    let intents =
+        serenity::GatewayIntents::non_privileged() | serenity::GatewayIntents::GUILD_MEMBERS;
 
     let framework = poise::Framework::<_, anyhow::Error>::builder()
         .options(poise::FrameworkOptions {
-            commands: vec![election()],
+            commands: vec![election(), verify_ballot()],
             event_handler: |ctx, event, framework, data| {
                 Box::pin(event_handler(ctx, event, framework, data))
             },
@@ -724,15 +2337,16 @@ async fn main() -> Result<(), anyhow::Error> {
         .setup(|ctx, _ready, framework| {
             Box::pin(async move {
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
-                let mut results: GlobalData<Elections> =
-                    if let Ok(contents) = std::fs::read_to_string("elections.json") {
-                        serde_json::from_str(&contents)?
-                    } else {
-                        GlobalData::default()
-                    };
+                let mut results: GlobalData<Elections> = match database {
+                    Some(database) => GlobalData::load_sqlite(database, "elections", data_key)?,
+                    None => GlobalData::load_json("elections", data_key)?,
+                };
                 results.migrate();
-                let _ = results.persist("elections");
-                Ok(GlobalState::new(results))
+                let _ = results.persist();
+                let state = GlobalState::new(results);
+                tokio::spawn(run_schedule(ctx.clone(), state.clone()));
+                tokio::spawn(shut_down(framework.shard_manager().clone(), state.clone()));
+                Ok(state)
             })
         })
         .build();
@@ -744,3 +2358,109 @@ async fn main() -> Result<(), anyhow::Error> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn ballot(rank: usize) -> election::Ballot {
+        election::Ballot {
+            votes: BTreeMap::from([("a".into(), rank)]),
+        }
+    }
+
+    fn vote(user: u64, election: actions::ElectionId, secret: bool) -> VoteInProgress {
+        VoteInProgress {
+            user: user.into(),
+            token: format!("token {user}"),
+            election,
+            election_message: 1.into(),
+            partial_ballot: ballot(2),
+            expires_at: Utc::now() + TimeDelta::hours(1),
+            secret,
+        }
+    }
+
+    /// A guild with an open and a secret election, each with ballots and a vote in progress.
+    fn data(key: &election::BallotKey) -> GlobalData<Elections> {
+        let mut data = GlobalData::<Elections>::default();
+        let guild = data.guild_mut(1.into()).latest();
+        for secret in [false, true] {
+            let mut election = election::Election::new(1, 1);
+            election.add_candidate("a", "EMEA");
+            if secret {
+                election.make_secret();
+            }
+            for voter in [2, 3] {
+                election
+                    .cast(voter.into(), ballot(1), Some(key), Utc::now())
+                    .unwrap();
+            }
+            let id = guild.elections.next_election_id.next();
+            guild.elections.elections.insert(id, election);
+            guild
+                .votes
+                .votes
+                .insert(guild.votes.next_vote_id.next(), vote(4, id, secret));
+        }
+        data
+    }
+
+    #[test]
+    fn test_elections_survive_a_round_trip_through_a_database() {
+        let key = election::BallotKey::new(b"key");
+        let mut data = data(&key);
+        let mut back = data.through_database().unwrap();
+        assert_eq!(
+            serde_json::to_value(&back).unwrap(),
+            serde_json::to_value(&data).unwrap()
+        );
+
+        let guild = back.guild_mut(1.into()).latest();
+        assert_eq!(guild.elections.elections.len(), 2);
+        for election in guild.elections.elections.values() {
+            election.verify_log().unwrap();
+            assert_eq!(election.log_len(), 2);
+            assert_eq!(
+                election.ballot_of(2.into(), Some(&key)).unwrap(),
+                Some(&ballot(1))
+            );
+        }
+        // Votes in progress on secret elections aren't saved.
+        let votes: Vec<_> = guild.votes.votes.values().collect();
+        assert_eq!(votes.len(), 1);
+        assert_eq!(votes[0].partial_ballot, ballot(2));
+        let guild = data.guild_mut(1.into()).latest();
+        guild.votes.votes.retain(|_, vote| !vote.secret);
+        assert_eq!(
+            serde_json::to_value(&back).unwrap(),
+            serde_json::to_value(&data).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_changed_ballots_and_votes_only_rewrite_their_rows() {
+        use data::Table;
+
+        let key = election::BallotKey::new(b"key");
+        let before = data(&key);
+
+        let changed = |pointer: &str, rank| {
+            let mut value = serde_json::to_value(&before).unwrap();
+            *value.pointer_mut(pointer).unwrap() = serde_json::to_value(ballot(rank)).unwrap();
+            let after: GlobalData<Elections> = serde_json::from_value(value).unwrap();
+            after.changed_rows(&before).unwrap()
+        };
+        // A ballot changed in place, leaving its election as it was.
+        assert_eq!(
+            changed("/guilds/1/elections/elections/0/ballots/2", 3),
+            vec![(Table::Ballots, "0/2".into(), true)]
+        );
+        assert_eq!(
+            changed("/guilds/1/votes/votes/0/partial_ballot", 3),
+            vec![(Table::Votes, "0".into(), true)]
+        );
+    }
+}